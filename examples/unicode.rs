@@ -0,0 +1,38 @@
+//! Example program showing text in several scripts, using a TrueType
+//! font as a composite font.
+extern crate pdf_canvas;
+
+use pdf_canvas::{Pdf, TrueTypeFont};
+use std::env;
+
+/// Create a `unicode.pdf` file, with a single page containing some
+/// lines of text in different scripts.
+///
+/// The font to use is given as a command line argument, and should be
+/// a TrueType font containing the glyphs needed, e.g. DejaVuSans.ttf.
+fn main() {
+    let fontfile =
+        env::args().nth(1).expect("Usage: unicode path/to/font.ttf");
+    let font = TrueTypeFont::from_file(&fontfile)
+        .expect("Read font file")
+        .composite();
+    let mut document = Pdf::create("unicode.pdf").unwrap();
+    document.set_title("Unicode example");
+    document
+        .render_page(300.0, 200.0, |c| {
+            let font = c.get_font(font.clone());
+            c.text(|t| {
+                t.set_font(&font, 14.0)?;
+                t.set_leading(20.0)?;
+                t.pos(10.0, 170.0)?;
+                t.show("Räksmörgås")?;
+                t.show_line("Zażółć gęślą jaźń")?;
+                t.show_line("Příliš žluťoučký kůň")?;
+                t.show_line("Γαζέες καὶ μυρτιὲς")?;
+                t.show_line("Съешь же ещё этих мягких")?;
+                t.show_line("∀ε > 0 ∃δ > 0")
+            })
+        })
+        .unwrap();
+    document.finish().unwrap();
+}
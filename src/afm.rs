@@ -0,0 +1,276 @@
+//! A parser for AFM (Adobe Font Metrics) files.
+//!
+//! This module is used both by the library and by the build script,
+//! so it should only depend on std.
+use std::io::{self, BufRead};
+
+/// The contents of an AFM file that is relevant for us.
+///
+/// Characters are given with their code in the encoding of the AFM
+/// file, and by name, so that they can be used with other encodings.
+#[derive(Debug, Default)]
+pub(crate) struct Afm {
+    pub(crate) font_name: Option<String>,
+    pub(crate) encoding_scheme: Option<String>,
+    pub(crate) is_fixed_pitch: bool,
+    pub(crate) std_vw: Option<f32>,
+    pub(crate) font_bbox: Option<[f32; 4]>,
+    pub(crate) ascender: Option<f32>,
+    pub(crate) descender: Option<f32>,
+    pub(crate) cap_height: Option<f32>,
+    pub(crate) x_height: Option<f32>,
+    pub(crate) italic_angle: Option<f32>,
+    pub(crate) chars: Vec<AfmChar>,
+    /// Kerning pairs, as (left name, right name, adjustment).
+    pub(crate) kerning: Vec<(String, String, f32)>,
+}
+
+/// The metrics of a single character in an AFM file.
+#[derive(Debug)]
+pub(crate) struct AfmChar {
+    /// The code of the character, -1 if it is not encoded.
+    pub(crate) code: i32,
+    pub(crate) width: f32,
+    pub(crate) name: Option<String>,
+    pub(crate) bbox: Option<[f32; 4]>,
+    /// Ligatures, as (successor name, ligature name).
+    pub(crate) ligatures: Vec<(String, String)>,
+}
+
+/// Parse an AFM file.
+///
+/// Keys that are not used are ignored, but a malformed value for a
+/// key that is used is an error, with the line number in the message.
+pub(crate) fn parse<R: BufRead>(source: R) -> io::Result<Afm> {
+    let mut afm = Afm::default();
+    let mut started = false;
+    // The line number of an unfinished StartCharMetrics.
+    let mut char_metrics = None;
+    for (i, line) in source.lines().enumerate() {
+        let line = line?;
+        let mut p = LineParser::new(i + 1, &line);
+        let key = match p.words.next() {
+            Some(key) => key,
+            None => continue, // A blank line
+        };
+        if !started {
+            if key != "StartFontMetrics" {
+                return Err(p.error("Not an AFM file"));
+            }
+            started = true;
+            continue;
+        }
+        match key {
+            "FontName" => afm.font_name = Some(p.word()?.to_string()),
+            "EncodingScheme" => {
+                afm.encoding_scheme = Some(p.word()?.to_string())
+            }
+            "IsFixedPitch" => afm.is_fixed_pitch = p.word()? == "true",
+            "StdVW" => afm.std_vw = Some(p.number()?),
+            "FontBBox" => afm.font_bbox = Some(p.bbox()?),
+            "Ascender" => afm.ascender = Some(p.number()?),
+            "Descender" => afm.descender = Some(p.number()?),
+            "CapHeight" => afm.cap_height = Some(p.number()?),
+            "XHeight" => afm.x_height = Some(p.number()?),
+            "ItalicAngle" => afm.italic_angle = Some(p.number()?),
+            "StartCharMetrics" => char_metrics = Some(i + 1),
+            "EndCharMetrics" => char_metrics = None,
+            "C" | "CH" => afm.chars.push(parse_char(i + 1, &line)?),
+            "KPX" | "KP" => {
+                let left = p.word()?.to_string();
+                let right = p.word()?.to_string();
+                afm.kerning.push((left, right, p.number()?));
+            }
+            "EndFontMetrics" => break,
+            _ => (),
+        }
+    }
+    if !started {
+        return Err(LineParser::new(1, "").error("Not an AFM file"));
+    }
+    if let Some(line_no) = char_metrics {
+        return Err(LineParser::new(line_no, "")
+            .error("StartCharMetrics without EndCharMetrics"));
+    }
+    Ok(afm)
+}
+
+/// Parse a line of character metrics, like
+/// `C 102 ; WX 333 ; N f ; B 20 0 383 683 ; L i fi ; L l fl ;`.
+fn parse_char(line_no: usize, line: &str) -> io::Result<AfmChar> {
+    let mut result = AfmChar {
+        code: -1,
+        width: 0.0,
+        name: None,
+        bbox: None,
+        ligatures: Vec::new(),
+    };
+    let mut has_code = false;
+    for item in line.split(';') {
+        let mut p = LineParser::new(line_no, item);
+        match p.words.next() {
+            Some("C") => {
+                result.code = p.word()?.parse().map_err(|_| {
+                    p.error(&format!("Bad character code in {:?}", item))
+                })?;
+                has_code = true;
+            }
+            Some("CH") => {
+                let hex = p.word()?;
+                result.code = hex
+                    .strip_prefix('<')
+                    .and_then(|hex| hex.strip_suffix('>'))
+                    .and_then(|hex| i32::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| {
+                        p.error(&format!("Bad character code in {:?}", item))
+                    })?;
+                has_code = true;
+            }
+            Some("WX") | Some("W0X") | Some("W") | Some("W0") => {
+                result.width = p.number()?
+            }
+            Some("N") => result.name = Some(p.word()?.to_string()),
+            Some("B") => result.bbox = Some(p.bbox()?),
+            Some("L") => {
+                let successor = p.word()?.to_string();
+                result.ligatures.push((successor, p.word()?.to_string()));
+            }
+            _ => (),
+        }
+    }
+    if !has_code {
+        return Err(LineParser::new(line_no, line)
+            .error("Character metrics without a code"));
+    }
+    Ok(result)
+}
+
+/// Helper for reading the values of a line (or part of a line).
+struct LineParser<'a> {
+    line_no: usize,
+    words: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(line_no: usize, line: &'a str) -> Self {
+        LineParser {
+            line_no,
+            words: line.split_whitespace(),
+        }
+    }
+
+    fn word(&mut self) -> io::Result<&'a str> {
+        self.words
+            .next()
+            .ok_or_else(|| self.error("Unexpected end of line"))
+    }
+
+    fn number(&mut self) -> io::Result<f32> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| self.error(&format!("Bad number {:?}", word)))
+    }
+
+    fn bbox(&mut self) -> io::Result<[f32; 4]> {
+        Ok([
+            self.number()?,
+            self.number()?,
+            self.number()?,
+            self.number()?,
+        ])
+    }
+
+    fn error(&self, message: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("AFM line {}: {}", self.line_no, message),
+        )
+    }
+}
+
+#[test]
+fn test_parse() {
+    let afm = parse(
+        "StartFontMetrics 4.1\n\
+         Comment Not a real font\n\
+         FontName Test-Roman\n\
+         Weight Medium\n\
+         FontBBox -168 -218 1000 898\n\
+         IsFixedPitch false\n\
+         Ascender 683\n\
+         Descender -217\n\
+         \n\
+         StartCharMetrics 3\n\
+         C 102 ; WX 333 ; N f ; B 20 0 383 683 ; L i fi ; L l fl ;\n\
+         CH <20> ; WX 250 ; N space ;\n\
+         C -1 ; WX 556 ; N fi ; B 31 0 521 683 ;\n\
+         EndCharMetrics\n\
+         StartKernData\n\
+         StartKernPairs 1\n\
+         KPX f i -20.5\n\
+         EndKernPairs\n\
+         EndKernData\n\
+         EndFontMetrics\n\
+         Ascender ignored after the end\n"
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(Some("Test-Roman"), afm.font_name.as_deref());
+    assert_eq!(Some([-168.0, -218.0, 1000.0, 898.0]), afm.font_bbox);
+    assert_eq!((Some(683.0), Some(-217.0)), (afm.ascender, afm.descender));
+    assert_eq!((None, None), (afm.cap_height, afm.x_height));
+    assert!(!afm.is_fixed_pitch);
+    assert_eq!(3, afm.chars.len());
+    let f = &afm.chars[0];
+    assert_eq!((102, 333.0), (f.code, f.width));
+    assert_eq!(Some([20.0, 0.0, 383.0, 683.0]), f.bbox);
+    assert_eq!(
+        vec![
+            ("i".to_string(), "fi".to_string()),
+            ("l".to_string(), "fl".to_string())
+        ],
+        f.ligatures
+    );
+    assert_eq!((32, None), (afm.chars[1].code, afm.chars[1].bbox));
+    assert_eq!(-1, afm.chars[2].code);
+    assert_eq!(Some("fi"), afm.chars[2].name.as_deref());
+    assert_eq!(vec![("f".to_string(), "i".to_string(), -20.5)], afm.kerning);
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = |afm: &str| parse(afm.as_bytes()).unwrap_err().to_string();
+    assert_eq!("AFM line 1: Not an AFM file", error(""));
+    assert_eq!("AFM line 1: Not an AFM file", error("FontName Foo\n"));
+    assert_eq!(
+        "AFM line 3: Bad number \"high\"",
+        error("StartFontMetrics 4.1\nFontName Foo\nAscender high\n")
+    );
+    assert_eq!(
+        "AFM line 2: Unexpected end of line",
+        error("StartFontMetrics 4.1\nFontBBox 0 0 500\n")
+    );
+    assert_eq!(
+        "AFM line 3: Bad character code in \"C x \"",
+        error("StartFontMetrics 4.1\nStartCharMetrics 1\nC x ; WX 1 ;\n")
+    );
+    assert_eq!(
+        "AFM line 3: Bad character code in \"CH 20 \"",
+        error("StartFontMetrics 4.1\nStartCharMetrics 1\nCH 20 ; WX 1 ;\n")
+    );
+    assert_eq!(
+        "AFM line 3: Bad number \"wide\"",
+        error("StartFontMetrics 4.1\nStartCharMetrics 1\nC 1 ; WX wide ;\n")
+    );
+    assert_eq!(
+        "AFM line 2: Bad number \"-2O\"",
+        error("StartFontMetrics 4.1\nKPX A V -2O\n")
+    );
+    assert_eq!(
+        "AFM line 2: StartCharMetrics without EndCharMetrics",
+        error(
+            "StartFontMetrics 4.1\nStartCharMetrics 1\n\
+             C 32 ; WX 250 ; N space ;\nEndFontMetrics\n"
+        )
+    );
+}
@@ -0,0 +1,101 @@
+//! Writing a pdf document to an asynchronous output.
+use crate::{Canvas, Pdf, Result};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// A PDF document written to a tokio `AsyncWrite`.
+///
+/// This is available with the `tokio` feature.
+///
+/// Each page is rendered by a synchronous [Canvas](struct.Canvas.html)
+/// into a buffer, and the buffer is written to the output when the
+/// page is done.
+/// Anything else that is added to the document, such as images, is
+/// written with the next page, or when the document is finished.
+///
+/// All other methods of [Pdf](struct.Pdf.html), such as metadata,
+/// settings, images and templates, are available through `Deref`.
+///
+/// # Example
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> pdf_canvas::Result<()> {
+/// use pdf_canvas::{AsyncPdf, BuiltinFont};
+///
+/// let output = tokio::fs::File::create("async.pdf").await?;
+/// let mut document = AsyncPdf::new(output)?;
+/// document.set_title("Written asynchronously");
+/// document
+///     .render_page(180.0, 240.0, |canvas| {
+///         let font = BuiltinFont::Times_Roman;
+///         canvas.left_text(10.0, 200.0, font, 14.0, "Async")
+///     })
+///     .await?;
+/// document.finish().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncPdf<W> {
+    pdf: Pdf<Vec<u8>>,
+    output: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncPdf<W> {
+    /// Create a new PDF document, writing to `output`.
+    pub fn new(output: W) -> Result<AsyncPdf<W>> {
+        Ok(AsyncPdf {
+            pdf: Pdf::new(Vec::new())?,
+            output,
+        })
+    }
+
+    /// Create a new page in the PDF document.
+    ///
+    /// This works like [Pdf::render_page](struct.Pdf.html#method.render_page),
+    /// and then writes everything buffered so far to the output.
+    pub async fn render_page<F>(
+        &mut self,
+        width: f32,
+        height: f32,
+        render_contents: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut Canvas) -> Result<()>,
+    {
+        self.pdf.render_page(width, height, render_contents)?;
+        self.write_buffered().await
+    }
+
+    /// Write the document trailer and flush the output.
+    ///
+    /// Return the output, when everything is written.
+    pub async fn finish(self) -> Result<W> {
+        let AsyncPdf { pdf, mut output } = self;
+        output.write_all(&pdf.finish()?).await?;
+        output.flush().await?;
+        Ok(output)
+    }
+
+    /// Write everything buffered by the document so far to the output.
+    ///
+    /// The inner `Pdf` keeps counting its bytes, so offsets stay right.
+    async fn write_buffered(&mut self) -> Result<()> {
+        let buffered = mem::take(&mut self.pdf.output.inner);
+        self.output.write_all(&buffered).await?;
+        Ok(())
+    }
+}
+
+impl<W> Deref for AsyncPdf<W> {
+    type Target = Pdf<Vec<u8>>;
+    fn deref(&self) -> &Pdf<Vec<u8>> {
+        &self.pdf
+    }
+}
+
+impl<W> DerefMut for AsyncPdf<W> {
+    fn deref_mut(&mut self) -> &mut Pdf<Vec<u8>> {
+        &mut self.pdf
+    }
+}
@@ -0,0 +1,166 @@
+//! Files attached to a document, as embedded file streams.
+use crate::textstring::{text_string, text_string_bytes};
+use crate::{pdf_date, Pdf, PdfA, PdfError, PdfOutput, Result};
+use chrono::{DateTime, FixedOffset};
+use std::fmt;
+use std::io::Write;
+
+/// How an attached file relates to the document, see
+/// [Pdf::attach_file](struct.Pdf.html#method.attach_file).
+///
+/// This is the `/AFRelationship` of the file, as used by PDF/A-3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relationship {
+    /// The original source of the document, e.g. a word processor file
+    /// that the document was made from.
+    Source,
+    /// Data that the document shows, e.g. the values of a table.
+    Data,
+    /// An alternative representation of the document, e.g. the
+    /// machine-readable XML of an invoice.
+    Alternative,
+    /// A supplement to the document, e.g. an XML version of some part
+    /// of it.
+    Supplement,
+    /// None of the above, or not known.
+    Unspecified,
+}
+
+impl fmt::Display for Relationship {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Relationship::Source => "Source",
+            Relationship::Data => "Data",
+            Relationship::Alternative => "Alternative",
+            Relationship::Supplement => "Supplement",
+            Relationship::Unspecified => "Unspecified",
+        })
+    }
+}
+
+/// A file to attach to a document when it is finished.
+pub(crate) struct Attachment {
+    name: String,
+    data: Vec<u8>,
+    mime: String,
+    relationship: Relationship,
+}
+
+impl Attachment {
+    /// Create an attachment, checking that it is allowed in a document
+    /// at the given PDF/A level, if any.
+    pub(crate) fn new(
+        name: &str,
+        data: &[u8],
+        mime: &str,
+        relationship: Relationship,
+        pdf_a: Option<PdfA>,
+    ) -> Result<Attachment> {
+        if name.is_empty() {
+            return Err(PdfError::InvalidArgument(
+                "An attached file needs a name".to_string(),
+            ));
+        }
+        let mut parts = mime.split('/');
+        let valid_mime = matches!(parts.next(), Some(t) if !t.is_empty())
+            && matches!(parts.next(), Some(t) if !t.is_empty())
+            && parts.next().is_none();
+        if !valid_mime {
+            return Err(PdfError::InvalidArgument(format!(
+                "Bad MIME type {:?}",
+                mime
+            )));
+        }
+        match pdf_a {
+            Some(PdfA::A1b) => {
+                return Err(PdfError::Conformance(
+                    "Attached files are not allowed in PDF/A-1b".to_string(),
+                ))
+            }
+            Some(PdfA::A2b) if mime != "application/pdf" => {
+                return Err(PdfError::Conformance(format!(
+                    "Only PDF/A files can be attached in PDF/A-2b, \
+                     not {}, use PDF/A-3b for other files",
+                    mime
+                )))
+            }
+            _ => (),
+        }
+        Ok(Attachment {
+            name: name.to_string(),
+            data: data.to_vec(),
+            mime: mime.to_string(),
+            relationship,
+        })
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Write the embedded file streams and file specifications for the
+/// `attachments`.
+///
+/// Return the name tree entries, the name of each file and the object
+/// id of its file specification, sorted by name.
+pub(crate) fn write_attachments(
+    pdf: &mut Pdf<dyn PdfOutput + '_>,
+    attachments: Vec<Attachment>,
+    mod_date: &DateTime<FixedOffset>,
+) -> Result<Vec<(String, usize)>> {
+    let mut entries = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let file_id = pdf.write_stream(
+            &format!(
+                "/Type /EmbeddedFile /Subtype {}\n   \
+                 /Params << /Size {} /ModDate ({}) >>",
+                pdf_name(&attachment.mime),
+                attachment.data.len(),
+                pdf_date(mod_date),
+            ),
+            &attachment.data,
+        )?;
+        let name = text_string(&attachment.name);
+        let spec_id = pdf.write_new_object(|spec_id, output| {
+            write!(output, "<< /Type /Filespec /F ")?;
+            output.write_all(&name)?;
+            write!(output, " /UF ")?;
+            output.write_all(&name)?;
+            writeln!(
+                output,
+                "\n   /AFRelationship /{}\n   \
+                 /EF << /F {id} 0 R /UF {id} 0 R >>\n\
+                 >>",
+                attachment.relationship,
+                id = file_id,
+            )?;
+            Ok(spec_id)
+        })?;
+        entries.push((attachment.name, spec_id));
+    }
+    // The names of a name tree are sorted by the bytes of the strings.
+    entries.sort_by_cached_key(|(name, _)| text_string_bytes(name));
+    Ok(entries)
+}
+
+/// Get `text` as a pdf name, including the leading slash.
+fn pdf_name(text: &str) -> String {
+    let mut result = String::from("/");
+    for byte in text.bytes() {
+        match byte {
+            b'!'..=b'~' if !b"#%()/<>[]{}".contains(&byte) => {
+                result.push(char::from(byte))
+            }
+            byte => result.push_str(&format!("#{:02X}", byte)),
+        }
+    }
+    result
+}
+
+#[test]
+fn test_pdf_name() {
+    assert_eq!("/text#2Fxml", pdf_name("text/xml"));
+    assert_eq!("/a#20b#23c", pdf_name("a b#c"));
+    assert_eq!("/#C3#A5", pdf_name("å"));
+}
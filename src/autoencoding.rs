@@ -0,0 +1,234 @@
+//! Automatic encodings for the builtin fonts, so that text can use
+//! every glyph in the fonts, not only those in WinAnsiEncoding.
+use crate::encoding::{Encoding, WIN_ANSI_ENCODING};
+use crate::fontmetrics::{get_builtin_glyphs, FontMetrics};
+use crate::fontsource::{BuiltinFont, EncodedBuiltinFont, FontSource};
+use crate::{Pdf, PdfOutput, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// The automatic encoding of a builtin font in a document.
+///
+/// An encoding has at most 256 codes, so the font may be shown through
+/// several font dictionaries, called parts here.
+/// The first part uses WinAnsiEncoding, with its unused codes given to
+/// other glyphs.
+/// Further parts use codes 33 to 255 for other glyphs, so that no glyph
+/// gets the word spacing of the space character.
+pub(crate) struct AutoEncoding {
+    font: BuiltinFont,
+    metrics: Arc<CharMetrics>,
+    /// The font registry index of each part, and its differences from
+    /// WinAnsiEncoding.
+    parts: Vec<(usize, Vec<(u8, &'static str)>)>,
+    /// The part and code for each character that is not in
+    /// WinAnsiEncoding, by the time it is first shown.
+    codes: BTreeMap<char, (usize, u8)>,
+    /// The codes that are still free in the last part, last first.
+    free: Vec<u8>,
+}
+
+impl AutoEncoding {
+    /// Create the automatic encoding for `font`, with its first part
+    /// at `index` in the font registry.
+    pub(crate) fn new(font: BuiltinFont, index: usize) -> Self {
+        AutoEncoding {
+            font,
+            metrics: Arc::new(CharMetrics::new(font)),
+            parts: vec![(index, Vec::new())],
+            codes: BTreeMap::new(),
+            free: (0..=255)
+                .rev()
+                .filter(|&code| WIN_ANSI_ENCODING.decode_char(code).is_none())
+                .collect(),
+        }
+    }
+
+    /// Get the font to use while the document is written, for the
+    /// part with the given number.
+    pub(crate) fn part_font(&self, part: usize) -> AutoFont {
+        AutoFont {
+            font: self.font,
+            part,
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Check if a character can be shown, in any part.
+    pub(crate) fn can_encode(&self, ch: char) -> bool {
+        !ch.is_control()
+            && (WIN_ANSI_ENCODING.encode_char(ch).is_some()
+                || self.metrics.glyphs.contains_key(&ch))
+    }
+
+    /// Get the font registry index of a part.
+    pub(crate) fn index(&self, part: usize) -> usize {
+        self.parts[part].0
+    }
+
+    /// Get the part number for a font registry index, if the index is
+    /// a part of this font.
+    pub(crate) fn part_of(&self, index: usize) -> Option<usize> {
+        self.parts.iter().position(|&(i, _)| i == index)
+    }
+
+    /// Get the part number and code for showing `ch`.
+    ///
+    /// A code is given to a character the first time it is shown.
+    /// If there are no free codes left, `new_part` is called to get a
+    /// font registry index for a new part.
+    /// Characters that are not in the font, see
+    /// [can_encode](#method.can_encode), are given a question mark.
+    pub(crate) fn encode_char<P>(
+        &mut self,
+        ch: char,
+        new_part: P,
+    ) -> (usize, u8)
+    where
+        P: FnOnce(AutoFont) -> usize,
+    {
+        if ch.is_control() {
+            // WinAnsiEncoding maps some control characters to the free
+            // codes that are used for other glyphs here.
+            return (0, b'?');
+        }
+        if let Some(code) = WIN_ANSI_ENCODING.encode_char(ch) {
+            return (0, code);
+        }
+        if let Some(&part_code) = self.codes.get(&ch) {
+            return part_code;
+        }
+        let name = match self.metrics.glyphs.get(&ch) {
+            Some(&(name, _)) => name,
+            None => return (0, b'?'),
+        };
+        let code = match self.free.pop() {
+            Some(code) => code,
+            None => {
+                let index = new_part(self.part_font(self.parts.len()));
+                self.parts.push((index, Vec::new()));
+                self.free = (33..=255).rev().collect();
+                self.free.pop().unwrap()
+            }
+        };
+        let part = self.parts.len() - 1;
+        self.parts[part].1.push((code, name));
+        self.codes.insert(ch, (part, code));
+        (part, code)
+    }
+
+    /// Get the font to write for each part, with the final encoding
+    /// for all text shown in the document, by font registry index.
+    pub(crate) fn final_fonts(
+        &self,
+    ) -> impl Iterator<Item = (usize, EncodedBuiltinFont)> + '_ {
+        self.parts.iter().map(move |(index, differences)| {
+            let encoding = WIN_ANSI_ENCODING.with_differences(differences);
+            (*index, self.font.with_encoding(encoding))
+        })
+    }
+}
+
+/// The widths and kerning of all glyphs in a builtin font, by unicode
+/// character.
+struct CharMetrics {
+    glyphs: BTreeMap<char, (&'static str, u16)>,
+    kerning: HashMap<(char, char), i16>,
+}
+
+impl CharMetrics {
+    fn new(font: BuiltinFont) -> Self {
+        let glyphs = get_builtin_glyphs(font);
+        CharMetrics {
+            glyphs: glyphs
+                .chars()
+                .map(|(ch, name, width)| (ch, (name, width)))
+                .collect(),
+            kerning: glyphs
+                .char_kerning()
+                .map(|(left, right, k)| ((left, right), k))
+                .collect(),
+        }
+    }
+}
+
+/// A part of a builtin font with an automatic encoding, as used while
+/// the document is written.
+///
+/// The widths and kerning are for all glyphs in the font, so that any
+/// text can be measured, whichever part it is shown in.
+/// When the document is finished, each part is written as an
+/// EncodedBuiltinFont with the final encoding for the part.
+pub(crate) struct AutoFont {
+    font: BuiltinFont,
+    part: usize,
+    metrics: Arc<CharMetrics>,
+}
+
+impl FontSource for AutoFont {
+    /// The parts are replaced by their final fonts before the fonts of
+    /// a document are written, so this is only a fallback, that shows
+    /// the WinAnsiEncoding part of the font.
+    fn write_object(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        chars: &BTreeSet<char>,
+    ) -> Result<usize> {
+        self.font.write_object(pdf, chars)
+    }
+
+    fn pdf_name(&self) -> String {
+        format!("{}+Auto{}", self.font.pdf_name(), self.part)
+    }
+
+    fn get_encoding(&self) -> &Encoding {
+        &WIN_ANSI_ENCODING
+    }
+
+    fn pdf_string(&self, text: &str) -> Vec<u8> {
+        self.font.pdf_string(text)
+    }
+
+    fn get_width(&self, size: f32, text: &str) -> f32 {
+        size * self.get_width_raw(text) as f32 / 1000.0
+    }
+
+    fn get_width_raw(&self, text: &str) -> u32 {
+        text.chars().fold(0, |result, ch| {
+            result
+                + match self.metrics.glyphs.get(&ch) {
+                    Some(&(_, width)) => u32::from(width),
+                    // Characters without a named glyph, like the no
+                    // break space, are measured as by WinAnsiEncoding.
+                    None => self.font.get_width_raw(&ch.to_string()),
+                }
+        })
+    }
+
+    fn get_kerning(&self, left: char, right: char) -> i32 {
+        let kerning = self.metrics.kerning.get(&(left, right));
+        i32::from(kerning.cloned().unwrap_or(0))
+    }
+
+    fn get_metrics(&self) -> FontMetrics {
+        self.font.get_metrics()
+    }
+}
+
+/// Get a pdf literal string for codes in a simple font.
+///
+/// Control codes are written as octal escapes, since line breaks in a
+/// literal string are not kept as they are.
+pub(crate) fn pdf_string(codes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(codes.len() + 2);
+    result.push(b'(');
+    for &code in codes {
+        match code {
+            b'\\' | b'(' | b')' => result.extend_from_slice(&[b'\\', code]),
+            0..=31 => result.extend(format!("\\{:03o}", code).bytes()),
+            _ => result.push(code),
+        }
+    }
+    result.push(b')');
+    result
+}
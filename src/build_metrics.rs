@@ -1,86 +1,129 @@
-#[macro_use]
-extern crate lazy_static;
-
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Result, Write};
+use std::io::{BufReader, Result, Write};
 use std::path::Path;
 
 #[allow(dead_code)]
-mod encoding;
-use encoding::{
-    Encoding, SYMBOL_ENCODING, WIN_ANSI_ENCODING, ZAPFDINGBATS_ENCODING,
-};
+mod afm;
 
-fn write_cond(f: &mut File, name: &str, encoding: &Encoding) -> Result<()> {
+/// Write the glyphs of a builtin font, by name, so that metrics for
+/// any encoding can be created from them.
+fn write_glyphs(f: &mut File, name: &str) -> Result<()> {
+    let filename = format!("data/{}.afm", name.replace("_", "-"));
+    println!("cargo:rerun-if-changed={}", filename);
+    let afm = afm::parse(BufReader::new(File::open(filename)?))?;
+    let chars = afm
+        .chars
+        .iter()
+        .filter_map(|c| Some((c.name.as_ref()?.as_str(), c)))
+        .collect::<Vec<_>>();
+    let index = chars
+        .iter()
+        .enumerate()
+        .map(|(i, &(name, _))| (name, i))
+        .collect::<BTreeMap<_, _>>();
+    let index = |name: &str| index.get(name).cloned();
     write!(
         f,
-        "  static ref METRICS_{name}: FontMetrics = \
-         FontMetrics::from_slice(&[",
+        "static GLYPHS_{name}: BuiltinGlyphs = BuiltinGlyphs {{\n    \
+         font_bbox: {:?},\n    \
+         ascender: {:?},\n    \
+         descender: {:?},\n    \
+         cap_height: {:?},\n    \
+         x_height: {:?},\n    \
+         italic_angle: {:?},\n    \
+         glyphs: &[",
+        afm.font_bbox,
+        afm.ascender,
+        afm.descender,
+        afm.cap_height,
+        afm.x_height,
+        afm.italic_angle.unwrap_or(0.0),
         name = name.to_uppercase()
     )?;
-    let filename = format!("data/{}.afm", name.replace("_", "-"));
-    println!("cargo:rerun-if-changed={}", filename);
-    let afm_file = File::open(filename)?;
-    for lineresult in BufReader::new(afm_file).lines() {
-        let line = lineresult?;
-        let words: Vec<&str> = line.split_whitespace().collect();
-        if words[0] == "C" && words[3] == "WX" && words[6] == "N" {
-            if let (Some(c), Ok(w)) =
-                (encoding.get_code(words[7]), words[4].parse::<u16>())
-            {
-                write!(f, "({}, {}), ", c, w)?;
+    for (name, metrics) in &chars {
+        write!(
+            f,
+            "({:?}, {}, {:?}), ",
+            name,
+            metrics.width.round() as u16,
+            metrics.bbox,
+        )?;
+    }
+    write!(f, "],\n    kerning: &[")?;
+    for (left, right, k) in &afm.kerning {
+        if let (Some(l), Some(r)) = (index(left), index(right)) {
+            write!(f, "({}, {}, {}), ", l, r, k.round() as i16)?;
+        }
+    }
+    write!(f, "],\n    ligatures: &[")?;
+    for (i, (_, metrics)) in chars.iter().enumerate() {
+        for (successor, ligature) in &metrics.ligatures {
+            if let (Some(s), Some(l)) = (index(successor), index(ligature)) {
+                write!(f, "({}, {}, {}), ", i, s, l)?;
             }
         }
     }
-    writeln!(f, "]);")
+    writeln!(f, "],\n}};")
 }
 
 fn main() {
     let dst =
         Path::new(&env::var("OUT_DIR").unwrap()).join("metrics_data.rs");
     let f = &mut File::create(&dst).unwrap();
-    let textfonts = [
-        "Courier",
-        "Courier_Bold",
-        "Courier_Oblique",
-        "Courier_BoldOblique",
-        "Helvetica",
-        "Helvetica_Bold",
-        "Helvetica_Oblique",
-        "Helvetica_BoldOblique",
-        "Times_Roman",
-        "Times_Bold",
-        "Times_Italic",
-        "Times_BoldItalic",
+    let fonts = [
+        ("Courier", "WIN_ANSI_ENCODING"),
+        ("Courier_Bold", "WIN_ANSI_ENCODING"),
+        ("Courier_Oblique", "WIN_ANSI_ENCODING"),
+        ("Courier_BoldOblique", "WIN_ANSI_ENCODING"),
+        ("Helvetica", "WIN_ANSI_ENCODING"),
+        ("Helvetica_Bold", "WIN_ANSI_ENCODING"),
+        ("Helvetica_Oblique", "WIN_ANSI_ENCODING"),
+        ("Helvetica_BoldOblique", "WIN_ANSI_ENCODING"),
+        ("Times_Roman", "WIN_ANSI_ENCODING"),
+        ("Times_Bold", "WIN_ANSI_ENCODING"),
+        ("Times_Italic", "WIN_ANSI_ENCODING"),
+        ("Times_BoldItalic", "WIN_ANSI_ENCODING"),
+        ("Symbol", "SYMBOL_ENCODING"),
+        ("ZapfDingbats", "ZAPFDINGBATS_ENCODING"),
     ];
-    writeln!(
-        f,
-        "pub fn get_builtin_metrics(font: BuiltinFont) \
-         -> &'static FontMetrics {{\n\
-         match font {{"
-    )
-    .unwrap();
-    for font in textfonts.iter().chain(["Symbol", "ZapfDingbats"].iter()) {
+    for (getter, kind, prefix) in &[
+        ("get_builtin_glyphs", "BuiltinGlyphs", "GLYPHS"),
+        ("get_builtin_metrics", "FontMetrics", "METRICS"),
+    ] {
         writeln!(
             f,
-            "BuiltinFont::{} => &METRICS_{},",
-            font,
-            font.to_uppercase(),
+            "pub(crate) fn {}(font: BuiltinFont) -> &'static {} {{\n\
+             match font {{",
+            getter, kind,
         )
         .unwrap();
+        for (font, _) in &fonts {
+            writeln!(
+                f,
+                "BuiltinFont::{} => &{}_{},",
+                font,
+                prefix,
+                font.to_uppercase(),
+            )
+            .unwrap();
+        }
+        writeln!(f, "}}\n}}").unwrap();
     }
-    writeln!(
-        f,
-        "}}\n\
-         }}\n\
-         lazy_static! {{",
-    )
-    .unwrap();
-    for font in &textfonts {
-        write_cond(f, font, &WIN_ANSI_ENCODING).unwrap();
+    writeln!(f, "lazy_static! {{").unwrap();
+    for (font, encoding) in &fonts {
+        writeln!(
+            f,
+            "  static ref METRICS_{name}: FontMetrics = \
+             GLYPHS_{name}.metrics(&{});",
+            encoding,
+            name = font.to_uppercase(),
+        )
+        .unwrap();
     }
-    write_cond(f, "Symbol", &SYMBOL_ENCODING).unwrap();
-    write_cond(f, "ZapfDingbats", &ZAPFDINGBATS_ENCODING).unwrap();
     writeln!(f, "}}").unwrap();
+    for (font, _) in &fonts {
+        write_glyphs(f, font).unwrap();
+    }
 }
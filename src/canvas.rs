@@ -1,36 +1,57 @@
-use crate::fontref::FontRef;
-use crate::fontsource::{BuiltinFont, FontSource};
+use crate::error::{check_finite, PdfError, Result};
+use crate::fontref::{FontRef, FontRegistry};
+use crate::fontsource::FontSource;
 use crate::graphicsstate::*;
+use crate::image::ImageRef;
 use crate::outline::OutlineItem;
+use crate::template::TemplateRef;
 use crate::textobject::TextObject;
-use std::collections::HashMap;
-use std::io::{self, Write};
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::io::Write;
 
 /// A visual area where content can be drawn (a page).
 ///
 /// Provides methods for defining and stroking or filling paths, as
 /// well as placing text objects.
 ///
-/// TODO Everything here that takes a `BuiltinFont` should take any
-/// `FontSource` instead.
+/// Coordinates and sizes must be finite numbers, or the drawing
+/// methods return a
+/// [PdfError::InvalidArgument](enum.PdfError.html#variant.InvalidArgument).
 pub struct Canvas<'a> {
     output: &'a mut dyn Write,
-    fonts: &'a mut HashMap<BuiltinFont, FontRef>,
+    fonts: &'a mut FontRegistry,
     outline_items: &'a mut Vec<OutlineItem>,
+    xobjects: &'a mut BTreeMap<String, usize>,
+    /// The number of graphics states saved and not yet restored.
+    saved_states: usize,
 }
 
 impl<'a> Canvas<'a> {
     // Should not be called by user code.
     pub(crate) fn new(
         output: &'a mut dyn Write,
-        fonts: &'a mut HashMap<BuiltinFont, FontRef>,
+        fonts: &'a mut FontRegistry,
         outline_items: &'a mut Vec<OutlineItem>,
+        xobjects: &'a mut BTreeMap<String, usize>,
     ) -> Self {
         Canvas {
             output,
             fonts,
             outline_items,
+            xobjects,
+            saved_states: 0,
+        }
+    }
+
+    /// Check that every saved graphics state has been restored, when
+    /// the canvas is done.
+    pub(crate) fn check_restored(&self) -> Result<()> {
+        match self.saved_states {
+            0 => Ok(()),
+            n => Err(PdfError::Structure(format!(
+                "{} gsave without a matching grestore",
+                n
+            ))),
         }
     }
 
@@ -42,14 +63,13 @@ impl<'a> Canvas<'a> {
         y: f32,
         width: f32,
         height: f32,
-    ) -> io::Result<()> {
-        writeln!(self.output, "{} {} {} {} re", x, y, width, height)
+    ) -> Result<()> {
+        check_finite(&[x, y, width, height])?;
+        writeln!(self.output, "{} {} {} {} re", x, y, width, height)?;
+        Ok(())
     }
     /// Set the line join style in the graphics state.
-    pub fn set_line_join_style(
-        &mut self,
-        style: JoinStyle,
-    ) -> io::Result<()> {
+    pub fn set_line_join_style(&mut self, style: JoinStyle) -> Result<()> {
         writeln!(
             self.output,
             "{} j",
@@ -58,10 +78,11 @@ impl<'a> Canvas<'a> {
                 JoinStyle::Round => 1,
                 JoinStyle::Bevel => 2,
             }
-        )
+        )?;
+        Ok(())
     }
     /// Set the line join style in the graphics state.
-    pub fn set_line_cap_style(&mut self, style: CapStyle) -> io::Result<()> {
+    pub fn set_line_cap_style(&mut self, style: CapStyle) -> Result<()> {
         writeln!(
             self.output,
             "{} J",
@@ -70,14 +91,17 @@ impl<'a> Canvas<'a> {
                 CapStyle::Round => 1,
                 CapStyle::ProjectingSquare => 2,
             }
-        )
+        )?;
+        Ok(())
     }
     /// Set the line width in the graphics state.
-    pub fn set_line_width(&mut self, w: f32) -> io::Result<()> {
-        writeln!(self.output, "{} w", w)
+    pub fn set_line_width(&mut self, w: f32) -> Result<()> {
+        check_finite(&[w])?;
+        writeln!(self.output, "{} w", w)?;
+        Ok(())
     }
     /// Set color for stroking operations.
-    pub fn set_stroke_color(&mut self, color: Color) -> io::Result<()> {
+    pub fn set_stroke_color(&mut self, color: Color) -> Result<()> {
         let norm = |c| f32::from(c) / 255.0;
         match color {
             Color::RGB { red, green, blue } => writeln!(
@@ -86,12 +110,15 @@ impl<'a> Canvas<'a> {
                 norm(red),
                 norm(green),
                 norm(blue),
-            ),
-            Color::Gray { gray } => writeln!(self.output, "{} G", norm(gray)),
+            )?,
+            Color::Gray { gray } => {
+                writeln!(self.output, "{} G", norm(gray))?
+            }
         }
+        Ok(())
     }
     /// Set color for non-stroking operations.
-    pub fn set_fill_color(&mut self, color: Color) -> io::Result<()> {
+    pub fn set_fill_color(&mut self, color: Color) -> Result<()> {
         let norm = |c| f32::from(c) / 255.0;
         match color {
             Color::RGB { red, green, blue } => writeln!(
@@ -100,36 +127,39 @@ impl<'a> Canvas<'a> {
                 norm(red),
                 norm(green),
                 norm(blue),
-            ),
-            Color::Gray { gray } => writeln!(self.output, "{} g", norm(gray)),
+            )?,
+            Color::Gray { gray } => {
+                writeln!(self.output, "{} g", norm(gray))?
+            }
         }
+        Ok(())
     }
 
     /// Modify the current transformation matrix for coordinates by
     /// concatenating the specified matrix.
-    pub fn concat(&mut self, m: Matrix) -> io::Result<()> {
-        writeln!(self.output, "{} cm", m)
+    pub fn concat(&mut self, m: Matrix) -> Result<()> {
+        check_finite(m.values())?;
+        writeln!(self.output, "{} cm", m)?;
+        Ok(())
     }
 
     /// Append a straight line from (x1, y1) to (x2, y2) to the current path.
-    pub fn line(
-        &mut self,
-        x1: f32,
-        y1: f32,
-        x2: f32,
-        y2: f32,
-    ) -> io::Result<()> {
+    pub fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) -> Result<()> {
         self.move_to(x1, y1)?;
         self.line_to(x2, y2)
     }
     /// Begin a new subpath at the point (x, y).
-    pub fn move_to(&mut self, x: f32, y: f32) -> io::Result<()> {
-        write!(self.output, "{} {} m ", x, y)
+    pub fn move_to(&mut self, x: f32, y: f32) -> Result<()> {
+        check_finite(&[x, y])?;
+        write!(self.output, "{} {} m ", x, y)?;
+        Ok(())
     }
     /// Add a straight line from the current point to (x, y) to the
     /// current path.
-    pub fn line_to(&mut self, x: f32, y: f32) -> io::Result<()> {
-        write!(self.output, "{} {} l ", x, y)
+    pub fn line_to(&mut self, x: f32, y: f32) -> Result<()> {
+        check_finite(&[x, y])?;
+        write!(self.output, "{} {} l ", x, y)?;
+        Ok(())
     }
     /// Add a Bézier curve from the current point to (x3, y3) with
     /// (x1, y1) and (x2, y2) as Bézier controll points.
@@ -141,13 +171,15 @@ impl<'a> Canvas<'a> {
         y2: f32,
         x3: f32,
         y3: f32,
-    ) -> io::Result<()> {
-        writeln!(self.output, "{} {} {} {} {} {} c", x1, y1, x2, y2, x3, y3)
+    ) -> Result<()> {
+        check_finite(&[x1, y1, x2, y2, x3, y3])?;
+        writeln!(self.output, "{} {} {} {} {} {} c", x1, y1, x2, y2, x3, y3)?;
+        Ok(())
     }
     /// Add a circle approximated by four cubic Bézier curves to the
     /// current path.  Based on
     /// http://spencermortensen.com/articles/bezier-circle/
-    pub fn circle(&mut self, x: f32, y: f32, r: f32) -> io::Result<()> {
+    pub fn circle(&mut self, x: f32, y: f32, r: f32) -> Result<()> {
         let top = y - r;
         let bottom = y + r;
         let left = x - r;
@@ -166,30 +198,56 @@ impl<'a> Canvas<'a> {
         self.curve_to(right, up, rightp, top, x, top)
     }
     /// Stroke the current path.
-    pub fn stroke(&mut self) -> io::Result<()> {
-        writeln!(self.output, "S")
+    pub fn stroke(&mut self) -> Result<()> {
+        writeln!(self.output, "S")?;
+        Ok(())
     }
     /// Close and stroke the current path.
-    pub fn close_and_stroke(&mut self) -> io::Result<()> {
-        writeln!(self.output, "s")
+    pub fn close_and_stroke(&mut self) -> Result<()> {
+        writeln!(self.output, "s")?;
+        Ok(())
     }
     /// Fill the current path.
-    pub fn fill(&mut self) -> io::Result<()> {
-        writeln!(self.output, "f")
+    pub fn fill(&mut self) -> Result<()> {
+        writeln!(self.output, "f")?;
+        Ok(())
     }
     /// Get a FontRef for a specific font.
-    pub fn get_font(&mut self, font: BuiltinFont) -> FontRef {
-        let next_n = self.fonts.len();
-        self.fonts
-            .entry(font)
-            .or_insert_with(|| {
-                FontRef::new(
-                    next_n,
-                    font.get_encoding().clone(),
-                    Arc::new(font.get_metrics()),
-                )
-            })
-            .clone()
+    ///
+    /// The font can be any [FontSource](trait.FontSource.html), such as
+    /// a [BuiltinFont](enum.BuiltinFont.html), a
+    /// [TrueTypeFont](struct.TrueTypeFont.html) or a
+    /// [Type1Font](struct.Type1Font.html).
+    /// Trait objects can be used as boxed fonts or `Arc`s.
+    ///
+    /// Fonts are identified by their
+    /// [pdf_name](trait.FontSource.html#tymethod.pdf_name), so getting
+    /// the same font again, on this page or another page of the same
+    /// document, will give an equivalent FontRef, and the font is only
+    /// written once in the document.
+    ///
+    /// # Example
+    /// ```
+    /// # use pdf_canvas::{Pdf, BuiltinFont, FontSource};
+    /// use std::sync::Arc;
+    /// let fonts: Vec<Arc<dyn FontSource>> = vec![
+    ///     Arc::new(BuiltinFont::Helvetica),
+    ///     Arc::new(BuiltinFont::Times_Roman),
+    /// ];
+    /// let mut document = Pdf::create("fonts.pdf").unwrap();
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     for (i, font) in fonts.iter().enumerate() {
+    ///         let y = 200.0 - 20.0 * i as f32;
+    ///         canvas.left_text(10.0, y, font.clone(), 12.0, "Hello")?;
+    ///     }
+    ///     assert_eq!(canvas.get_font(BuiltinFont::Helvetica),
+    ///                canvas.get_font(fonts[0].clone()));
+    ///     Ok(())
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn get_font<F: FontSource + 'static>(&mut self, font: F) -> FontRef {
+        self.fonts.get_font(font)
     }
 
     /// Create a text object.
@@ -198,24 +256,26 @@ impl<'a> Canvas<'a> {
     /// render_text, by applying methods to the TextObject it gets as
     /// an argument.
     /// On success, return the value returned by render_text.
-    pub fn text<F, T>(&mut self, render_text: F) -> io::Result<T>
+    pub fn text<F, T>(&mut self, render_text: F) -> Result<T>
     where
-        F: FnOnce(&mut TextObject) -> io::Result<T>,
+        F: FnOnce(&mut TextObject) -> Result<T>,
     {
         writeln!(self.output, "BT")?;
-        let result = render_text(&mut TextObject::new(self.output))?;
+        let mut text_object = TextObject::new(self.output, self.fonts);
+        let result = render_text(&mut text_object)?;
+        text_object.check_restored()?;
         writeln!(self.output, "ET")?;
         Ok(result)
     }
     /// Utility method for placing a string of text.
-    pub fn left_text(
+    pub fn left_text<F: FontSource + 'static>(
         &mut self,
         x: f32,
         y: f32,
-        font: BuiltinFont,
+        font: F,
         size: f32,
         text: &str,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         let font = self.get_font(font);
         self.text(|t| {
             t.set_font(&font, size)?;
@@ -224,14 +284,14 @@ impl<'a> Canvas<'a> {
         })
     }
     /// Utility method for placing a string of text.
-    pub fn right_text(
+    pub fn right_text<F: FontSource + 'static>(
         &mut self,
         x: f32,
         y: f32,
-        font: BuiltinFont,
+        font: F,
         size: f32,
         text: &str,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         let font = self.get_font(font);
         self.text(|t| {
             let text_width = font.get_width(size, text);
@@ -241,14 +301,14 @@ impl<'a> Canvas<'a> {
         })
     }
     /// Utility method for placing a string of text.
-    pub fn center_text(
+    pub fn center_text<F: FontSource + 'static>(
         &mut self,
         x: f32,
         y: f32,
-        font: BuiltinFont,
+        font: F,
         size: f32,
         text: &str,
-    ) -> io::Result<()> {
+    ) -> Result<()> {
         let font = self.get_font(font);
         self.text(|t| {
             let text_width = font.get_width(size, text);
@@ -258,6 +318,46 @@ impl<'a> Canvas<'a> {
         })
     }
 
+    /// Draw an image, with the lower left corner at (x, y), scaled to
+    /// width × height.
+    ///
+    /// The image is added to the document by
+    /// [Pdf::add_jpeg](struct.Pdf.html#method.add_jpeg) or
+    /// [Pdf::add_png](struct.Pdf.html#method.add_png).
+    pub fn draw_image(
+        &mut self,
+        image: &ImageRef,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) -> Result<()> {
+        check_finite(&[x, y, width, height])?;
+        self.xobjects.insert(image.to_string(), image.id());
+        writeln!(
+            self.output,
+            "q\n{} 0 0 {} {} {} cm\n{} Do\nQ",
+            width, height, x, y, image,
+        )?;
+        Ok(())
+    }
+
+    /// Draw a template, transformed by `matrix`.
+    ///
+    /// The template is rendered by
+    /// [Pdf::render_template](struct.Pdf.html#method.render_template),
+    /// and the matrix maps its coordinates to those of this canvas.
+    pub fn draw_template(
+        &mut self,
+        template: &TemplateRef,
+        matrix: Matrix,
+    ) -> Result<()> {
+        check_finite(matrix.values())?;
+        self.xobjects.insert(template.to_string(), template.id());
+        writeln!(self.output, "q\n{} cm\n{} Do\nQ", matrix, template)?;
+        Ok(())
+    }
+
     /// Add an item for this page in the document outline.
     ///
     /// An outline item associates a name (contained in an ordered
@@ -270,13 +370,25 @@ impl<'a> Canvas<'a> {
     }
 
     /// Save the current graphics state.
-    /// The caller is responsible for restoring it later.
-    pub fn gsave(&mut self) -> io::Result<()> {
-        writeln!(self.output, "q")
+    /// The caller is responsible for restoring it later, before the
+    /// page is done.
+    pub fn gsave(&mut self) -> Result<()> {
+        writeln!(self.output, "q")?;
+        self.saved_states += 1;
+        Ok(())
     }
     /// Restor the current graphics state.
-    /// The caller is responsible for having saved it earlier.
-    pub fn grestore(&mut self) -> io::Result<()> {
-        writeln!(self.output, "Q")
+    /// The caller is responsible for having saved it earlier, or a
+    /// [PdfError::Structure](enum.PdfError.html#variant.Structure) is
+    /// returned.
+    pub fn grestore(&mut self) -> Result<()> {
+        if self.saved_states == 0 {
+            return Err(PdfError::Structure(
+                "grestore without a matching gsave".to_string(),
+            ));
+        }
+        writeln!(self.output, "Q")?;
+        self.saved_states -= 1;
+        Ok(())
     }
 }
@@ -4,9 +4,12 @@ use std::collections::BTreeMap;
 /// An encoding maintains the connection between unicode code points,
 /// bytes in PDF strings, and glyph names.
 ///
-/// Currently, only WIN_ANSI_ENCODING, SYMBOL_ENCODING, and
-/// ZAPFDINGBATS_ENCODING are supported, and they are provided as
-/// built-in.
+/// The encodings of the builtin fonts, WinAnsiEncoding,
+/// SymbolEncoding, and ZapfDingbatsEncoding, are provided as built-in.
+/// Other encodings can be created from them with
+/// [with_differences](#method.with_differences).
+/// Composite fonts use the Identity-H encoding, where text is shown
+/// as glyph ids rather than through the encoding.
 ///
 /// # Example
 /// ````
@@ -19,8 +22,13 @@ use std::collections::BTreeMap;
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Encoding {
     name: String,
-    name_to_code: BTreeMap<&'static str, u8>,
+    name_to_code: BTreeMap<String, u8>,
     unicode_to_code: BTreeMap<char, u8>,
+    /// Glyph names for codes that differ from the base encoding.
+    differences: BTreeMap<u8, String>,
+    /// True for the encoding built in to a specific font, that can not
+    /// be named as the base encoding of an encoding dictionary.
+    font_specific: bool,
 }
 
 impl Encoding {
@@ -68,6 +76,15 @@ impl Encoding {
         self.unicode_to_code.get(&ch).cloned()
     }
 
+    /// Get the (unicode) character for an encoded code point.
+    /// If the code is not used in the encoding, None is returned.
+    pub(crate) fn decode_char(&self, code: u8) -> Option<char> {
+        self.unicode_to_code
+            .iter()
+            .find(|&(ch, &c)| c == code && !ch.is_control())
+            .map(|(&ch, _)| ch)
+    }
+
     /// Convert a rust string to a vector of bytes in the encoding.
     /// # Example
     /// ````
@@ -112,20 +129,264 @@ impl Encoding {
         result
     }
 
+    /// Create an encoding with the codes of this encoding, except for
+    /// the `differences`, that give the glyph name for some codes.
+    ///
+    /// The glyph names of the Adobe standard Latin character set, like
+    /// "Lslash" or "scaron", and names like "uni0151" are mapped to
+    /// their unicode characters, so that text can be encoded with the
+    /// new encoding.
+    /// Other glyph names are written to the font, but no character is
+    /// encoded as those codes.
+    ///
+    /// When the encoding is used by a font, it is written as an
+    /// encoding dictionary with this encoding as its base encoding
+    /// and a Differences array.
+    ///
+    /// # Example
+    /// ````
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// let enc = BuiltinFont::Times_Roman.get_encoding()
+    ///     .with_differences(&[(128, "Lslash"), (129, "uni0151")]);
+    /// assert_eq!(Some(128), enc.encode_char('Ł'));
+    /// assert_eq!(Some(129), enc.encode_char('ő'));
+    /// assert_eq!(Some(128), enc.get_code("Lslash"));
+    /// assert_eq!(Some(b'A'), enc.encode_char('A'));
+    /// assert_eq!(None, enc.encode_char('€')); // Was code 128.
+    /// assert_eq!("WinAnsiEncoding", enc.get_name());
+    /// ````
+    pub fn with_differences(&self, differences: &[(u8, &str)]) -> Encoding {
+        let mut result = self.clone();
+        for &(code, name) in differences {
+            result.name_to_code.retain(|_, c| *c != code);
+            result.unicode_to_code.retain(|_, c| *c != code);
+            result.name_to_code.insert(name.to_string(), code);
+            if let Some(ch) = glyph_char(name) {
+                result.unicode_to_code.insert(ch, code);
+            }
+            result.differences.insert(code, name.to_string());
+        }
+        result
+    }
+
+    /// Create the built-in encoding of a symbolic font, from the code
+    /// and glyph name of each character in the font.
+    ///
+    /// Glyph names from SymbolEncoding and ZapfDingbatsEncoding are
+    /// mapped to unicode as in those encodings.
+    pub(crate) fn font_specific<'a, I>(name: &str, glyphs: I) -> Encoding
+    where
+        I: IntoIterator<Item = (u8, &'a str)>,
+    {
+        let mut result = Encoding {
+            name: name.to_string(),
+            name_to_code: BTreeMap::new(),
+            unicode_to_code: BTreeMap::new(),
+            differences: BTreeMap::new(),
+            font_specific: true,
+        };
+        for (code, glyph) in glyphs {
+            result.name_to_code.insert(glyph.to_string(), code);
+            let ch = glyph_char(glyph).or_else(|| {
+                [&*SYMBOL_ENCODING, &*ZAPFDINGBATS_ENCODING]
+                    .iter()
+                    .find_map(|enc| {
+                        // Skip the code itself, that all codes map from.
+                        let code = enc.get_code(glyph)?;
+                        enc.unicode_to_code
+                            .iter()
+                            .find(|&(&ch, &c)| c == code && ch != code.into())
+                            .map(|(&ch, _)| ch)
+                    })
+            });
+            if let Some(ch) = ch {
+                result.unicode_to_code.entry(ch).or_insert(code);
+            }
+        }
+        result
+    }
+
+    /// Get the value for the /Encoding of a simple font using this
+    /// encoding.
+    ///
+    /// This is the name of the encoding, or an encoding dictionary if
+    /// the encoding has differences.
+    pub(crate) fn pdf_object(&self) -> String {
+        if self.differences.is_empty() {
+            return format!("/{}", self.name);
+        }
+        let mut result = "<< /Type /Encoding".to_string();
+        if !self.font_specific {
+            result.push_str(" /BaseEncoding /");
+            result.push_str(&self.name);
+        }
+        result.push_str("\n   /Differences [");
+        let mut next = None;
+        for (&code, name) in &self.differences {
+            if next != Some(code) {
+                result.push_str(&format!(" {}", code));
+            }
+            result.push_str(" /");
+            for byte in name.bytes() {
+                // Delimiters, whitespace and non-ascii must be escaped
+                // in a pdf name.
+                if byte.is_ascii_graphic() && !b"()<>[]{}/%#".contains(&byte)
+                {
+                    result.push(char::from(byte));
+                } else {
+                    result.push_str(&format!("#{:02X}", byte));
+                }
+            }
+            next = code.checked_add(1);
+        }
+        result.push_str(" ] >>");
+        result
+    }
+
     fn init_block(&mut self, start: u8, data: &[&'static str]) {
         for (i, name) in data.iter().enumerate() {
-            self.name_to_code.insert(name, start + (i as u8));
+            self.name_to_code
+                .insert(name.to_string(), start + (i as u8));
+        }
+    }
+}
+
+/// Get the unicode character for a glyph name.
+///
+/// This handles the names in WinAnsiEncoding, the other names of the
+/// Adobe standard Latin character set, and names like "uni0141" and
+/// "u1F600".
+pub(crate) fn glyph_char(name: &str) -> Option<char> {
+    if !name.starts_with("..") {
+        if let Some(code) = WIN_ANSI_ENCODING.get_code(name) {
+            return WIN_ANSI_ENCODING.decode_char(code);
         }
     }
+    if let Ok(i) = LATIN_GLYPHS.binary_search_by_key(&name, |&(n, _)| n) {
+        return Some(LATIN_GLYPHS[i].1);
+    }
+    let hex = match (name.strip_prefix("uni"), name.strip_prefix('u')) {
+        (Some(hex), _) if hex.len() == 4 => hex,
+        (_, Some(hex)) if (4..=6).contains(&hex.len()) => hex,
+        _ => return None,
+    };
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
 }
 
+/// The glyphs of the Adobe standard Latin character set that are not
+/// in WinAnsiEncoding, sorted by name.
+static LATIN_GLYPHS: &[(&str, char)] = &[
+    ("Abreve", 'Ă'),
+    ("Amacron", 'Ā'),
+    ("Aogonek", 'Ą'),
+    ("Cacute", 'Ć'),
+    ("Ccaron", 'Č'),
+    ("Dcaron", 'Ď'),
+    ("Dcroat", 'Đ'),
+    ("Delta", '∆'),
+    ("Ecaron", 'Ě'),
+    ("Edotaccent", 'Ė'),
+    ("Emacron", 'Ē'),
+    ("Eogonek", 'Ę'),
+    ("Gbreve", 'Ğ'),
+    ("Gcommaaccent", 'Ģ'),
+    ("Idotaccent", 'İ'),
+    ("Imacron", 'Ī'),
+    ("Iogonek", 'Į'),
+    ("Kcommaaccent", 'Ķ'),
+    ("Lacute", 'Ĺ'),
+    ("Lcaron", 'Ľ'),
+    ("Lcommaaccent", 'Ļ'),
+    ("Lslash", 'Ł'),
+    ("Nacute", 'Ń'),
+    ("Ncaron", 'Ň'),
+    ("Ncommaaccent", 'Ņ'),
+    ("Ohungarumlaut", 'Ő'),
+    ("Omacron", 'Ō'),
+    ("Racute", 'Ŕ'),
+    ("Rcaron", 'Ř'),
+    ("Rcommaaccent", 'Ŗ'),
+    ("Sacute", 'Ś'),
+    ("Scedilla", 'Ş'),
+    ("Scommaaccent", 'Ș'),
+    ("Tcaron", 'Ť'),
+    ("Tcommaaccent", 'Ţ'),
+    ("Uhungarumlaut", 'Ű'),
+    ("Umacron", 'Ū'),
+    ("Uogonek", 'Ų'),
+    ("Uring", 'Ů'),
+    ("Zacute", 'Ź'),
+    ("Zdotaccent", 'Ż'),
+    ("abreve", 'ă'),
+    ("amacron", 'ā'),
+    ("aogonek", 'ą'),
+    ("breve", '˘'),
+    ("cacute", 'ć'),
+    ("caron", 'ˇ'),
+    ("ccaron", 'č'),
+    ("commaaccent", '\u{326}'),
+    ("dcaron", 'ď'),
+    ("dcroat", 'đ'),
+    ("dotaccent", '˙'),
+    ("dotlessi", 'ı'),
+    ("ecaron", 'ě'),
+    ("edotaccent", 'ė'),
+    ("emacron", 'ē'),
+    ("eogonek", 'ę'),
+    ("fi", 'ﬁ'),
+    ("fl", 'ﬂ'),
+    ("fraction", '⁄'),
+    ("gbreve", 'ğ'),
+    ("gcommaaccent", 'ģ'),
+    ("greaterequal", '≥'),
+    ("hungarumlaut", '˝'),
+    ("imacron", 'ī'),
+    ("iogonek", 'į'),
+    ("kcommaaccent", 'ķ'),
+    ("lacute", 'ĺ'),
+    ("lcaron", 'ľ'),
+    ("lcommaaccent", 'ļ'),
+    ("lessequal", '≤'),
+    ("lozenge", '◊'),
+    ("lslash", 'ł'),
+    ("minus", '−'),
+    ("nacute", 'ń'),
+    ("ncaron", 'ň'),
+    ("ncommaaccent", 'ņ'),
+    ("notequal", '≠'),
+    ("ogonek", '˛'),
+    ("ohungarumlaut", 'ő'),
+    ("omacron", 'ō'),
+    ("partialdiff", '∂'),
+    ("racute", 'ŕ'),
+    ("radical", '√'),
+    ("rcaron", 'ř'),
+    ("rcommaaccent", 'ŗ'),
+    ("ring", '˚'),
+    ("sacute", 'ś'),
+    ("scedilla", 'ş'),
+    ("scommaaccent", 'ș'),
+    ("summation", '∑'),
+    ("tcaron", 'ť'),
+    ("tcommaaccent", 'ţ'),
+    ("uhungarumlaut", 'ű'),
+    ("umacron", 'ū'),
+    ("uogonek", 'ų'),
+    ("uring", 'ů'),
+    ("zacute", 'ź'),
+    ("zdotaccent", 'ż'),
+];
+
 lazy_static! {
     pub static ref WIN_ANSI_ENCODING: Encoding = {
         let mut codes = BTreeMap::new();
         // /WinAnsiEncoding is kind of close to first byte of unicode
         // Except for the 16 chars that are reserved in 8859-1 and
         // used in Windows-1252.
-        for code in 32..255 {
+        for code in 32..=255 {
             codes.insert(code as char, code);
         }
         codes.insert('€', 128);
@@ -152,12 +413,15 @@ lazy_static! {
         codes.insert('™', 153);
         codes.insert('š', 154);
         codes.insert('›', 155);
+        codes.insert('œ', 156);
         codes.insert('ž', 158);
         codes.insert('Ÿ', 159);
         let mut result = Encoding {
             name: "WinAnsiEncoding".to_string(),
             name_to_code: BTreeMap::new(),
-            unicode_to_code: codes
+            unicode_to_code: codes,
+            differences: BTreeMap::new(),
+            font_specific: false,
         };
         result.init_block(0o40, &[
             "space", "exclam", "quotedbl", "numbersign",
@@ -243,7 +507,7 @@ lazy_static! {
         {
             let mut enc = |ch: char, name: &'static str, code: u8| {
                 codes.insert(ch, code);
-                names.insert(name, code);
+                names.insert(name.to_string(), code);
             };
             enc('Α', "Alpha",          0o101);
             enc('Β', "Beta",           0o102);
@@ -440,10 +704,22 @@ lazy_static! {
         Encoding {
             name: "SymbolEncoding".to_string(),
             name_to_code: names,
-            unicode_to_code: codes
+            unicode_to_code: codes,
+            differences: BTreeMap::new(),
+            font_specific: true,
         }
     };
 
+    // Composite fonts show text as two-byte glyph ids, so this encoding
+    // has no single-byte codes, only the name.
+    pub static ref IDENTITY_H_ENCODING: Encoding = Encoding {
+        name: "Identity-H".to_string(),
+        name_to_code: BTreeMap::new(),
+        unicode_to_code: BTreeMap::new(),
+        differences: BTreeMap::new(),
+        font_specific: false,
+    };
+
     // https://unicode.org/Public/MAPPINGS/VENDORS/ADOBE/zdingbat.txt
     pub static ref ZAPFDINGBATS_ENCODING: Encoding = {
         let mut codes = BTreeMap::new();
@@ -454,7 +730,7 @@ lazy_static! {
         {
             let mut enc = |ch: char, name: &'static str, code: u8| {
                 codes.insert(ch, code);
-                names.insert(name, code);
+                names.insert(name.to_string(), code);
             };
             enc(' ', "space", 0o40);
             enc(' ', "space", 0o40);
@@ -663,11 +939,35 @@ lazy_static! {
         Encoding {
             name: "ZapfDingbatsEncoding".to_string(),
             name_to_code: names,
-            unicode_to_code: codes
+            unicode_to_code: codes,
+            differences: BTreeMap::new(),
+            font_specific: true,
         }
     };
 }
 
+#[test]
+fn test_latin_glyphs() {
+    use std::collections::BTreeSet;
+    assert!(LATIN_GLYPHS.windows(2).all(|w| w[0].0 < w[1].0));
+    // Every glyph in the builtin Latin fonts has a character.
+    let names = include_str!("../data/Times-Roman.afm")
+        .lines()
+        .filter_map(|line| line.split(" ; N ").nth(1))
+        .filter_map(|rest| rest.split(' ').next())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(315, names.len());
+    for name in names {
+        assert!(glyph_char(name).is_some(), "No char for {}", name);
+    }
+    assert_eq!(Some('œ'), glyph_char("oe"));
+    assert_eq!(Some('ÿ'), glyph_char("ydieresis"));
+    assert_eq!(Some('ő'), glyph_char("uni0151"));
+    assert_eq!(Some('😀'), glyph_char("u1F600"));
+    assert_eq!(None, glyph_char("uniXYZW"));
+    assert_eq!(None, glyph_char("..1"));
+}
+
 #[test]
 fn test_get_winansi_points() {
     let ref enc = WIN_ANSI_ENCODING;
@@ -0,0 +1,120 @@
+//! The error type of this crate.
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// An error when creating a pdf document.
+#[derive(Debug)]
+pub enum PdfError {
+    /// Reading or writing a file or other stream failed.
+    Io(io::Error),
+    /// A text can not be encoded for the font it is shown in, such as
+    /// a character that is not in the font.
+    Encoding(String),
+    /// An argument is invalid, such as a coordinate that is not a
+    /// finite number, a page size that is not positive, or font or
+    /// image data that can not be parsed.
+    InvalidArgument(String),
+    /// The document or page would not be properly structured, such as
+    /// when a `grestore` has no matching `gsave`.
+    Structure(String),
+    /// The document would not conform to the PDF/A level it is made
+    /// for, such as when a font is not embedded.
+    Conformance(String),
+}
+
+/// A result with a [PdfError](enum.PdfError.html).
+pub type Result<T, E = PdfError> = std::result::Result<T, E>;
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PdfError::Io(err) => err.fmt(f),
+            PdfError::Encoding(message) => {
+                write!(f, "Encoding error: {}", message)
+            }
+            PdfError::InvalidArgument(message) => {
+                write!(f, "Invalid argument: {}", message)
+            }
+            PdfError::Structure(message) => {
+                write!(f, "Structure error: {}", message)
+            }
+            PdfError::Conformance(message) => {
+                write!(f, "Conformance error: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for PdfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PdfError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PdfError {
+    fn from(err: io::Error) -> PdfError {
+        PdfError::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for PdfError {
+    fn from(err: png::DecodingError) -> PdfError {
+        match err {
+            png::DecodingError::IoError(err) => PdfError::Io(err),
+            err => PdfError::InvalidArgument(err.to_string()),
+        }
+    }
+}
+
+/// Convert back to an `io::Error`, for code that returns `io::Result`.
+impl From<PdfError> for io::Error {
+    fn from(err: PdfError) -> io::Error {
+        let kind = match err {
+            PdfError::Io(err) => return err,
+            PdfError::Encoding(_) => io::ErrorKind::InvalidData,
+            PdfError::InvalidArgument(_) => io::ErrorKind::InvalidInput,
+            PdfError::Structure(_) | PdfError::Conformance(_) => {
+                io::ErrorKind::Other
+            }
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Convert an error from reading font or image data, where invalid
+/// data is an invalid argument rather than an I/O error.
+pub(crate) fn data_error(err: io::Error) -> PdfError {
+    match err.kind() {
+        io::ErrorKind::InvalidData => {
+            PdfError::InvalidArgument(err.to_string())
+        }
+        _ => PdfError::Io(err),
+    }
+}
+
+/// Check that all `values` are finite numbers, as needed in a pdf
+/// file.
+pub(crate) fn check_finite(values: &[f32]) -> Result<()> {
+    match values.iter().find(|v| !v.is_finite()) {
+        Some(v) => Err(PdfError::InvalidArgument(format!(
+            "Expected a finite number, got {}",
+            v
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn test_check_finite() {
+    assert!(check_finite(&[0.0, -1.5, 1e30]).is_ok());
+    let err = check_finite(&[1.0, f32::NAN]).err().unwrap();
+    assert_eq!(
+        "Invalid argument: Expected a finite number, got NaN",
+        err.to_string()
+    );
+    assert!(check_finite(&[f32::INFINITY]).is_err());
+}
@@ -1,43 +1,149 @@
+use crate::afm::{self, Afm, AfmChar};
+use crate::encoding::{
+    glyph_char, Encoding, SYMBOL_ENCODING, WIN_ANSI_ENCODING,
+    ZAPFDINGBATS_ENCODING,
+};
+use crate::error::{data_error, Result};
 use crate::fontsource::BuiltinFont;
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, Read};
 
 /// Relevant data that can be loaded from an AFM (Adobe Font Metrics) file.
 /// A FontMetrics object is specific to a given encoding.
-#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+///
+/// All lengths are in thousands of unit of text space, and boxes are
+/// given as `[llx, lly, urx, ury]`, the lower left and upper right
+/// corners.
+#[derive(Debug, PartialEq, Clone)]
 pub struct FontMetrics {
     widths: BTreeMap<u8, u16>,
+    kerning: BTreeMap<(u8, u8), i16>,
+    glyph_boxes: BTreeMap<u8, [f32; 4]>,
+    ligatures: BTreeMap<(u8, u8), u8>,
+    font_bbox: Option<[f32; 4]>,
+    ascender: Option<f32>,
+    descender: Option<f32>,
+    cap_height: Option<f32>,
+    x_height: Option<f32>,
+    italic_angle: f32,
 }
 
 impl FontMetrics {
     /// Create a FontMetrics by reading an .afm file.
-    pub fn parse(source: File) -> io::Result<FontMetrics> {
-        let source = io::BufReader::new(source);
+    ///
+    /// The metrics are for the encoding of the file, as given by the
+    /// character codes in it.
+    /// A malformed file gives a `PdfError::InvalidArgument`, with the
+    /// line number of the problem in the message.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::FontMetrics;
+    /// let afm = "StartFontMetrics 4.1\n\
+    ///            Ascender 718\n\
+    ///            StartCharMetrics 1\n\
+    ///            C 32 ; WX 278 ; N space ; B 0 0 0 0 ;\n\
+    ///            EndCharMetrics\n\
+    ///            EndFontMetrics\n";
+    /// let metrics = FontMetrics::parse(afm.as_bytes()).unwrap();
+    /// assert_eq!(Some(278), metrics.get_width(32));
+    /// assert_eq!(Some(718.0), metrics.get_ascender());
+    ///
+    /// let err = FontMetrics::parse("StartFontMetrics 4.1\n\n\
+    ///                               C 32 ; WX wide ;".as_bytes());
+    /// assert_eq!("Invalid argument: AFM line 3: Bad number \"wide\"",
+    ///            err.unwrap_err().to_string());
+    /// ```
+    pub fn parse<R: Read>(source: R) -> Result<FontMetrics> {
+        let afm =
+            afm::parse(io::BufReader::new(source)).map_err(data_error)?;
+        Ok(FontMetrics::from_afm(&afm, |c| u8::try_from(c.code).ok()))
+    }
+
+    /// Create a FontMetrics from a parsed AFM file, with `code` giving
+    /// the code for each character in the AFM file, if any.
+    pub(crate) fn from_afm<C>(afm: &Afm, code: C) -> FontMetrics
+    where
+        C: Fn(&AfmChar) -> Option<u8>,
+    {
+        let codes = afm
+            .chars
+            .iter()
+            .filter_map(|c| Some((c.name.as_ref()?.as_str(), code(c)?)))
+            .collect::<BTreeMap<_, _>>();
         let mut result = FontMetrics {
-            widths: BTreeMap::new(),
+            font_bbox: afm.font_bbox,
+            ascender: afm.ascender,
+            descender: afm.descender,
+            cap_height: afm.cap_height,
+            x_height: afm.x_height,
+            italic_angle: afm.italic_angle.unwrap_or(0.0),
+            ..FontMetrics::from_slice(&[])
         };
-        for line in source.lines() {
-            let line = line.unwrap();
-            let words: Vec<&str> = line.split_whitespace().collect();
-            if words[0] == "C" && words[3] == "WX" {
-                if let (Ok(c), Ok(w)) =
-                    (words[1].parse::<u8>(), words[4].parse::<u16>())
-                {
-                    result.widths.insert(c, w);
+        for c in &afm.chars {
+            if let Some(code) = code(c) {
+                result.widths.insert(code, c.width.round() as u16);
+                if let Some(bbox) = c.bbox {
+                    result.glyph_boxes.insert(code, bbox);
+                }
+                for (successor, ligature) in &c.ligatures {
+                    if let (Some(&s), Some(&l)) = (
+                        codes.get(successor.as_str()),
+                        codes.get(ligature.as_str()),
+                    ) {
+                        result.ligatures.insert((code, s), l);
+                    }
                 }
             }
         }
-        Ok(result)
+        for (left, right, k) in &afm.kerning {
+            if let (Some(&l), Some(&r)) =
+                (codes.get(left.as_str()), codes.get(right.as_str()))
+            {
+                result.kerning.insert((l, r), k.round() as i16);
+            }
+        }
+        result
     }
 
     /// Create a FontMetrics from a slice of (char, width) pairs.
-    fn from_slice(data: &[(u8, u16)]) -> Self {
+    pub(crate) fn from_slice(data: &[(u8, u16)]) -> Self {
         let mut widths = BTreeMap::new();
         for &(c, w) in data {
             widths.insert(c, w);
         }
-        FontMetrics { widths }
+        FontMetrics {
+            widths,
+            kerning: BTreeMap::new(),
+            glyph_boxes: BTreeMap::new(),
+            ligatures: BTreeMap::new(),
+            font_bbox: None,
+            ascender: None,
+            descender: None,
+            cap_height: None,
+            x_height: None,
+            italic_angle: 0.0,
+        }
+    }
+
+    /// Set the metrics for the entire font, for metrics that are not
+    /// read from an AFM file.
+    pub(crate) fn with_font_info(
+        mut self,
+        font_bbox: [f32; 4],
+        ascender: f32,
+        descender: f32,
+        cap_height: f32,
+        x_height: Option<f32>,
+        italic_angle: f32,
+    ) -> Self {
+        self.font_bbox = Some(font_bbox);
+        self.ascender = Some(ascender);
+        self.descender = Some(descender);
+        self.cap_height = Some(cap_height);
+        self.x_height = x_height;
+        self.italic_angle = italic_angle;
+        self
     }
 
     /// Get the width of a specific character.
@@ -45,6 +151,188 @@ impl FontMetrics {
     pub fn get_width(&self, char: u8) -> Option<u16> {
         self.widths.get(&char).cloned()
     }
+
+    /// Get the kerning adjustment for a pair of characters, in
+    /// thousands of unit of text space.
+    /// The adjustment is added to the width of the left character, so
+    /// a negative adjustment brings the characters closer.
+    /// Zero is returned for a pair without kerning.
+    /// The characters are given in the encoding of the FontMetrics
+    /// object.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// let metrics = BuiltinFont::Helvetica.get_metrics();
+    /// assert_eq!(-70, metrics.get_kerning(b'A', b'V'));
+    /// assert_eq!(0, metrics.get_kerning(b'A', b'B'));
+    /// ```
+    pub fn get_kerning(&self, left: u8, right: u8) -> i32 {
+        i32::from(self.kerning.get(&(left, right)).cloned().unwrap_or(0))
+    }
+
+    /// Get the bounding box of a specific character.
+    /// The character is given in the encoding of the FontMetrics object.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// let metrics = BuiltinFont::Helvetica.get_metrics();
+    /// assert_eq!(Some([14.0, 0.0, 654.0, 718.0]), metrics.get_bbox(b'A'));
+    /// ```
+    pub fn get_bbox(&self, char: u8) -> Option<[f32; 4]> {
+        self.glyph_boxes.get(&char).cloned()
+    }
+
+    /// Get the ligature for a pair of characters, if the font has one.
+    /// The characters are given in the encoding of the FontMetrics
+    /// object.
+    ///
+    /// Note that the common ligatures, like "fi", are not in
+    /// WinAnsiEncoding, so the builtin fonts have no ligatures unless
+    /// used with an encoding that has them.
+    pub fn get_ligature(&self, char: u8, successor: u8) -> Option<u8> {
+        self.ligatures.get(&(char, successor)).cloned()
+    }
+
+    /// Get the bounding box of all glyphs in the font.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// let metrics = BuiltinFont::Times_Roman.get_metrics();
+    /// assert_eq!(Some([-168.0, -218.0, 1000.0, 898.0]),
+    ///            metrics.get_font_bbox());
+    /// ```
+    pub fn get_font_bbox(&self) -> Option<[f32; 4]> {
+        self.font_bbox
+    }
+
+    /// Get the ascender, the height of lowercase letters like "d".
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// let metrics = BuiltinFont::Helvetica.get_metrics();
+    /// assert_eq!(Some(718.0), metrics.get_ascender());
+    /// assert_eq!(Some(-207.0), metrics.get_descender());
+    /// assert_eq!(Some(718.0), metrics.get_cap_height());
+    /// assert_eq!(Some(523.0), metrics.get_x_height());
+    /// ```
+    pub fn get_ascender(&self) -> Option<f32> {
+        self.ascender
+    }
+
+    /// Get the descender, the (negative) depth of lowercase letters
+    /// like "p".
+    pub fn get_descender(&self) -> Option<f32> {
+        self.descender
+    }
+
+    /// Get the height of flat capital letters, like "H".
+    pub fn get_cap_height(&self) -> Option<f32> {
+        self.cap_height
+    }
+
+    /// Get the height of flat lowercase letters, like "x".
+    pub fn get_x_height(&self) -> Option<f32> {
+        self.x_height
+    }
+
+    /// Get the italic angle, in degrees counter-clockwise from the
+    /// vertical.
+    /// The angle is negative for fonts that slope to the right.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// assert_eq!(-15.5, BuiltinFont::Times_Italic.get_metrics()
+    ///                                            .get_italic_angle());
+    /// ```
+    pub fn get_italic_angle(&self) -> f32 {
+        self.italic_angle
+    }
+}
+
+/// The metrics of all glyphs in a builtin font, by glyph name, as read
+/// from its AFM file by the build script.
+///
+/// The FontMetrics of the font for any encoding are created from this.
+pub(crate) struct BuiltinGlyphs {
+    font_bbox: Option<[f32; 4]>,
+    ascender: Option<f32>,
+    descender: Option<f32>,
+    cap_height: Option<f32>,
+    x_height: Option<f32>,
+    italic_angle: f32,
+    /// The name, width and bounding box of each glyph.
+    glyphs: &'static [(&'static str, u16, Option<[f32; 4]>)],
+    /// Kerning pairs, as (left, right, adjustment), with the glyphs
+    /// given by their index in `glyphs`.
+    kerning: &'static [(u16, u16, i16)],
+    /// Ligatures, as (glyph, successor, ligature) indexes in `glyphs`.
+    ligatures: &'static [(u16, u16, u16)],
+}
+
+impl BuiltinGlyphs {
+    /// Get the metrics of the glyphs that have a code in `encoding`.
+    pub(crate) fn metrics(&self, encoding: &Encoding) -> FontMetrics {
+        let codes = self
+            .glyphs
+            .iter()
+            .map(|&(name, _, _)| encoding.get_code(name))
+            .collect::<Vec<_>>();
+        let code = |i: u16| codes[usize::from(i)];
+        let mut result = FontMetrics {
+            font_bbox: self.font_bbox,
+            ascender: self.ascender,
+            descender: self.descender,
+            cap_height: self.cap_height,
+            x_height: self.x_height,
+            italic_angle: self.italic_angle,
+            ..FontMetrics::from_slice(&[])
+        };
+        for (&(_, width, bbox), &c) in self.glyphs.iter().zip(&codes) {
+            if let Some(c) = c {
+                result.widths.insert(c, width);
+                if let Some(bbox) = bbox {
+                    result.glyph_boxes.insert(c, bbox);
+                }
+            }
+        }
+        for &(l, r, k) in self.kerning {
+            if let (Some(l), Some(r)) = (code(l), code(r)) {
+                result.kerning.insert((l, r), k);
+            }
+        }
+        for &(c, s, l) in self.ligatures {
+            if let (Some(c), Some(s), Some(l)) = (code(c), code(s), code(l)) {
+                result.ligatures.insert((c, s), l);
+            }
+        }
+        result
+    }
+
+    /// Get the glyphs that have a unicode character, as (character,
+    /// glyph name, width).
+    pub(crate) fn chars(
+        &self,
+    ) -> impl Iterator<Item = (char, &'static str, u16)> + '_ {
+        self.glyphs.iter().filter_map(|&(name, width, _)| {
+            Some((glyph_char(name)?, name, width))
+        })
+    }
+
+    /// Get the kerning pairs for glyphs that have a unicode character,
+    /// as (left, right, adjustment).
+    pub(crate) fn char_kerning(
+        &self,
+    ) -> impl Iterator<Item = (char, char, i16)> + '_ {
+        let char = |i: u16| glyph_char(self.glyphs[usize::from(i)].0);
+        self.kerning
+            .iter()
+            .filter_map(move |&(l, r, k)| Some((char(l)?, char(r)?, k)))
+    }
 }
 
 include!(concat!(env!("OUT_DIR"), "/metrics_data.rs"));
@@ -1,6 +1,11 @@
+use crate::autoencoding::{pdf_string, AutoEncoding};
 use crate::encoding::Encoding;
-use crate::fontmetrics::FontMetrics;
+use crate::error::{PdfError, Result};
+use crate::fontsource::{BuiltinFont, FontSource};
+use crate::pdfa::PdfA;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 /// A font ready to be used in a TextObject.
@@ -14,33 +19,31 @@ use std::sync::Arc;
 /// Calling Canvas::get_font will make sure the font dictionary is
 /// created in the file, associate it with a name in the page
 /// resources and return a FontRef representing that name.
+/// A FontRef can be used on any page of the document it was created
+/// for, and using it in another document is an error.
 ///
 /// The `serif` variable in
 /// [the TextObject example](struct.TextObject.html#example) is a FontRef.
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Clone)]
 pub struct FontRef {
     n: usize,
-    encoding: Encoding,
-    metrics: Arc<FontMetrics>,
+    font: Arc<dyn FontSource>,
 }
 
 impl FontRef {
     // Hidden from user code by not beeing a constructor method of FontRef.
-    pub(crate) fn new(
-        n: usize,
-        encoding: Encoding,
-        metrics: Arc<FontMetrics>,
-    ) -> Self {
-        FontRef {
-            n,
-            encoding,
-            metrics,
-        }
+    pub(crate) fn new(n: usize, font: Arc<dyn FontSource>) -> Self {
+        FontRef { n, font }
+    }
+
+    /// Get the index of the referenced font in its document.
+    pub(crate) fn index(&self) -> usize {
+        self.n
     }
 
     /// Get the encoding used by the referenced font.
     pub fn get_encoding(&self) -> &Encoding {
-        &self.encoding
+        self.font.get_encoding()
     }
 
     /// Get the width of the given text in this font at given size.
@@ -53,15 +56,53 @@ impl FontRef {
     /// This unit is what is used in some places internally in pdf files
     /// and in some methods on a [TextObject](struct.TextObject.html).
     pub fn get_width_raw(&self, text: &str) -> u32 {
-        text.chars().fold(0, |result, char| {
-            result
-                + u32::from(
-                    self.encoding
-                        .encode_char(char)
-                        .and_then(|ch| self.metrics.get_width(ch))
-                        .unwrap_or(100),
-                )
-        })
+        self.font.get_width_raw(text)
+    }
+
+    /// Get the kerning adjustment between two characters in this font,
+    /// in thousands of unit of text space.
+    pub fn get_kerning(&self, left: char, right: char) -> i32 {
+        self.font.get_kerning(left, right)
+    }
+
+    /// Get the width of the given text in this font at given size, when
+    /// shown with kerning.
+    pub fn get_kerned_width(&self, size: f32, text: &str) -> f32 {
+        self.font.get_kerned_width(size, text)
+    }
+
+    /// Get the width of the given text in thousands of unit of text
+    /// space, when shown with kerning.
+    pub fn get_kerned_width_raw(&self, text: &str) -> u32 {
+        self.font.get_kerned_width_raw(text)
+    }
+
+    /// Get the pdf string for showing `text` in the referenced font.
+    pub(crate) fn pdf_string(&self, text: &str) -> Vec<u8> {
+        self.font.pdf_string(text)
+    }
+}
+
+impl PartialEq for FontRef {
+    fn eq(&self, other: &FontRef) -> bool {
+        self.n == other.n && self.font.pdf_name() == other.font.pdf_name()
+    }
+}
+
+impl Eq for FontRef {}
+
+impl Hash for FontRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.n.hash(state);
+    }
+}
+
+impl fmt::Debug for FontRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FontRef")
+            .field("n", &self.n)
+            .field("font", &self.font.pdf_name())
+            .finish()
     }
 }
 
@@ -70,3 +111,203 @@ impl fmt::Display for FontRef {
         write!(f, "/F{}", self.n)
     }
 }
+
+/// The fonts used in a document.
+///
+/// Fonts are identified by their pdf name.
+/// For each font, the characters shown in it are recorded, so that
+/// the font can be written (possibly as a subset) when the document is
+/// finished.
+#[derive(Default)]
+pub(crate) struct FontRegistry {
+    fonts: Vec<(Arc<dyn FontSource>, BTreeSet<char>)>,
+    by_name: HashMap<String, usize>,
+    page_fonts: BTreeSet<usize>,
+    /// True if builtin fonts should get automatic encodings.
+    auto_encoding: bool,
+    /// The PDF/A level of the document, if any, where all fonts must
+    /// be embedded.
+    pdf_a: Option<PdfA>,
+    auto_fonts: HashMap<BuiltinFont, AutoEncoding>,
+}
+
+impl FontRegistry {
+    /// Set if builtin fonts that are used from now on should get
+    /// automatic encodings.
+    pub(crate) fn set_auto_encoding(&mut self, auto_encoding: bool) {
+        self.auto_encoding = auto_encoding;
+    }
+
+    /// Set the PDF/A level of the document, so that text can only be
+    /// shown in embedded fonts.
+    pub(crate) fn set_pdf_a(&mut self, pdf_a: PdfA) {
+        self.pdf_a = Some(pdf_a);
+    }
+
+    /// Check that a font can be used in the document.
+    pub(crate) fn check_font(&self, font: &dyn FontSource) -> Result<()> {
+        match self.pdf_a {
+            Some(pdf_a) if !font.is_embedded() => {
+                Err(PdfError::Conformance(format!(
+                    "The font {} is not embedded, as {} requires",
+                    font.pdf_name(),
+                    pdf_a
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Get a FontRef for a font, and mark it as used on the current page.
+    pub(crate) fn get_font<F: FontSource + 'static>(
+        &mut self,
+        font: F,
+    ) -> FontRef {
+        if self.auto_encoding {
+            match font.as_builtin() {
+                // Symbol and ZapfDingbats have all their glyphs in
+                // their own encodings.
+                Some(BuiltinFont::Symbol)
+                | Some(BuiltinFont::ZapfDingbats) => {}
+                Some(builtin) => return self.get_auto_font(builtin),
+                None => {}
+            }
+        }
+        let fonts = &mut self.fonts;
+        let n = *self.by_name.entry(font.pdf_name()).or_insert_with(|| {
+            fonts.push((Arc::new(font), BTreeSet::new()));
+            fonts.len() - 1
+        });
+        self.page_fonts.insert(n);
+        FontRef::new(n, self.fonts[n].0.clone())
+    }
+
+    /// Get a FontRef for the first part of a builtin font with an
+    /// automatic encoding.
+    fn get_auto_font(&mut self, font: BuiltinFont) -> FontRef {
+        let fonts = &mut self.fonts;
+        let auto = self.auto_fonts.entry(font).or_insert_with(|| {
+            let auto = AutoEncoding::new(font, fonts.len());
+            fonts.push((Arc::new(auto.part_font(0)), BTreeSet::new()));
+            auto
+        });
+        let n = auto.index(0);
+        self.page_fonts.insert(n);
+        FontRef::new(n, self.fonts[n].0.clone())
+    }
+
+    /// Check that a FontRef was created for this document.
+    fn check_ref(&self, font: &FontRef) -> Result<()> {
+        match self.fonts.get(font.n) {
+            Some((source, _)) if Arc::ptr_eq(source, &font.font) => Ok(()),
+            _ => Err(PdfError::InvalidArgument(format!(
+                "The font {} is from another document",
+                font.font.pdf_name()
+            ))),
+        }
+    }
+
+    /// Mark a font as used on the current page.
+    pub(crate) fn use_font(&mut self, font: &FontRef) -> Result<()> {
+        self.check_ref(font)?;
+        self.page_fonts.insert(font.n);
+        Ok(())
+    }
+
+    /// Get the pdf strings for showing `text` in a font, with the font
+    /// to show each string in, and record that the text is shown.
+    ///
+    /// All text is shown in the font itself, except for a builtin font
+    /// with an automatic encoding, that may need several parts.
+    /// It is an error if any character can not be shown in the font.
+    pub(crate) fn encode_text(
+        &mut self,
+        font: &FontRef,
+        text: &str,
+    ) -> Result<Vec<(FontRef, Vec<u8>)>> {
+        self.check_ref(font)?;
+        self.check_font(&*font.font)?;
+        let auto = self
+            .auto_fonts
+            .values_mut()
+            .find(|auto| auto.part_of(font.n).is_some());
+        let missing = match auto {
+            Some(ref auto) => text.chars().find(|&ch| !auto.can_encode(ch)),
+            None => text.chars().find(|&ch| !font.font.can_show(ch)),
+        };
+        if let Some(ch) = missing {
+            return Err(PdfError::Encoding(format!(
+                "{:?} can not be shown in the font {}",
+                ch,
+                font.font.pdf_name()
+            )));
+        }
+        let auto = match auto {
+            Some(auto) => auto,
+            None => {
+                if let Some((_, chars)) = self.fonts.get_mut(font.n) {
+                    chars.extend(text.chars());
+                }
+                return Ok(vec![(font.clone(), font.pdf_string(text))]);
+            }
+        };
+        let fonts = &mut self.fonts;
+        // The text, as (part, chars, codes) for each run of characters
+        // in the same part.
+        let mut runs: Vec<(usize, String, Vec<u8>)> = Vec::new();
+        for ch in text.chars() {
+            let (part, code) = auto.encode_char(ch, |part_font| {
+                fonts.push((Arc::new(part_font), BTreeSet::new()));
+                fonts.len() - 1
+            });
+            match runs.last_mut() {
+                Some((p, chars, codes)) if *p == part => {
+                    chars.push(ch);
+                    codes.push(code);
+                }
+                _ => runs.push((part, ch.to_string(), vec![code])),
+            }
+        }
+        if runs.is_empty() {
+            runs.push((auto.part_of(font.n).unwrap_or(0), "".into(), vec![]));
+        }
+        Ok(runs
+            .into_iter()
+            .map(|(part, chars, codes)| {
+                let n = auto.index(part);
+                self.fonts[n].1.extend(chars.chars());
+                self.page_fonts.insert(n);
+                (FontRef::new(n, self.fonts[n].0.clone()), pdf_string(&codes))
+            })
+            .collect())
+    }
+
+    /// Get the fonts used on the current page, and start a new page.
+    pub(crate) fn take_page_fonts(&mut self) -> Vec<FontRef> {
+        std::mem::take(&mut self.page_fonts)
+            .into_iter()
+            .map(|n| FontRef::new(n, self.fonts[n].0.clone()))
+            .collect()
+    }
+
+    /// Get the fonts used in the document so far.
+    pub(crate) fn fonts(&self) -> &[(Arc<dyn FontSource>, BTreeSet<char>)] {
+        &self.fonts
+    }
+
+    /// Get all fonts used in the document, with the characters used.
+    ///
+    /// The parts of builtin fonts with automatic encodings are given
+    /// with their final encodings.
+    pub(crate) fn into_fonts(
+        self,
+    ) -> Vec<(Arc<dyn FontSource>, BTreeSet<char>)> {
+        let mut fonts = self.fonts;
+        for auto in self.auto_fonts.values() {
+            for (n, font) in auto.final_fonts() {
+                fonts[n].0 = Arc::new(font);
+            }
+        }
+        fonts
+    }
+}
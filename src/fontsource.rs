@@ -1,11 +1,15 @@
 use crate::encoding::{
     Encoding, SYMBOL_ENCODING, WIN_ANSI_ENCODING, ZAPFDINGBATS_ENCODING,
 };
-use crate::fontmetrics::{get_builtin_metrics, FontMetrics};
-use crate::Pdf;
-use std::cmp::Eq;
-use std::hash::Hash;
-use std::io::{self};
+use crate::fontmetrics::{
+    get_builtin_glyphs, get_builtin_metrics, FontMetrics,
+};
+use crate::subset::fnv_hash;
+use crate::tounicode::{simple_font_codes, write_to_unicode};
+use crate::{Pdf, PdfOutput, Result};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::sync::Arc;
 
 /// The "Base14" built-in fonts in PDF.
 /// Underscores in these names are hyphens in the real names.
@@ -31,20 +35,84 @@ pub enum BuiltinFont {
 /// This trait is implemented by any kind of font that the pdf library
 /// supports.
 ///
-/// Currently, only BuiltinFont implements this.
-/// TODO Add implementation(s) for other fonts.
-pub trait FontSource: PartialEq + Eq + Hash {
+/// Currently, BuiltinFont, EncodedBuiltinFont, TrueTypeFont and
+/// Type1Font implements this.
+/// It is also implemented for boxed fonts and `Arc`s, including trait
+/// objects like `Arc<dyn FontSource>`.
+///
+/// Other kinds of fonts can be supported by implementing this trait
+/// outside of this crate, using
+/// [Pdf::write_raw_object](struct.Pdf.html#method.write_raw_object),
+/// [Pdf::write_stream](struct.Pdf.html#method.write_stream) and
+/// [Pdf::write_raw_stream](struct.Pdf.html#method.write_raw_stream)
+/// to write the font.
+///
+/// # Example
+/// ```
+/// use pdf_canvas::{
+///     BuiltinFont, Encoding, FontMetrics, FontSource, Pdf, PdfOutput,
+///     Result,
+/// };
+/// use std::collections::BTreeSet;
+///
+/// /// Courier, written with a font dictionary of our own.
+/// struct MyCourier;
+///
+/// impl FontSource for MyCourier {
+///     fn write_object(
+///         &self,
+///         pdf: &mut Pdf<dyn PdfOutput + '_>,
+///         _chars: &BTreeSet<char>,
+///     ) -> Result<usize> {
+///         pdf.write_raw_object(
+///             b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier \
+///               /Encoding /WinAnsiEncoding >>",
+///         )
+///     }
+///     fn pdf_name(&self) -> String {
+///         "MyCourier".to_string()
+///     }
+///     fn get_encoding(&self) -> &Encoding {
+///         BuiltinFont::Courier.get_encoding()
+///     }
+///     fn get_width(&self, size: f32, text: &str) -> f32 {
+///         BuiltinFont::Courier.get_width(size, text)
+///     }
+///     fn get_width_raw(&self, text: &str) -> u32 {
+///         BuiltinFont::Courier.get_width_raw(text)
+///     }
+///     fn get_metrics(&self) -> FontMetrics {
+///         BuiltinFont::Courier.get_metrics()
+///     }
+/// }
+///
+/// let mut document = Pdf::create("custom_font.pdf").unwrap();
+/// document.render_page(180.0, 240.0, |canvas| {
+///     canvas.left_text(10.0, 200.0, MyCourier, 12.0, "Hello World!")
+/// }).unwrap();
+/// document.finish().unwrap();
+/// ```
+pub trait FontSource {
     /// Write the object(s) for this font to a pdf file.
     ///
-    /// This is called automatically for each font used in a document.
+    /// This is called automatically for each font used in a document,
+    /// when the document is finished.
+    /// The `chars` are all characters shown in this font in the
+    /// document, so a font that is embedded can be written as a subset,
+    /// and a ToUnicode CMap can map the codes shown back to the chars.
     /// There should be no need to call this method from user code.
-    fn write_object<F: std::io::Write + std::io::Read + std::io::Seek>(
+    fn write_object(
         &self,
-        pdf: &mut Pdf<F>,
-    ) -> io::Result<usize>;
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        chars: &BTreeSet<char>,
+    ) -> Result<usize>;
 
     /// Get the PDF name of this font.
     ///
+    /// Fonts are identified by this name within a document, so two
+    /// different fonts used in the same document must not have the same
+    /// name.
+    ///
     /// # Examples
     /// ```
     /// use pdf_canvas::{BuiltinFont, FontSource};
@@ -55,6 +123,76 @@ pub trait FontSource: PartialEq + Eq + Hash {
     /// Get the encoding that this font uses.
     fn get_encoding(&self) -> &Encoding;
 
+    /// Get the pdf string for showing `text` in this font, including
+    /// the string delimiters.
+    ///
+    /// The default implementation encodes the text with the encoding of
+    /// the font, as a literal string.
+    ///
+    /// # Examples
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// assert_eq!(b"(x \\(y\\))".to_vec(),
+    ///            BuiltinFont::Courier.pdf_string("x (y)"));
+    /// ```
+    fn pdf_string(&self, text: &str) -> Vec<u8> {
+        let mut result = vec![b'('];
+        result.extend(self.get_encoding().encode_string(text));
+        result.push(b')');
+        result
+    }
+
+    /// Check if a character can be shown in this font.
+    ///
+    /// Showing a text with a character that can not be shown is an
+    /// [encoding error](enum.PdfError.html#variant.Encoding).
+    /// The default implementation checks that the character is in the
+    /// encoding of the font.
+    ///
+    /// # Examples
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// assert!(BuiltinFont::Times_Roman.can_show('é'));
+    /// assert!(!BuiltinFont::Times_Roman.can_show('Ł'));
+    /// ```
+    fn can_show(&self, ch: char) -> bool {
+        self.get_encoding().encode_char(ch).is_some()
+    }
+
+    /// Check if the font program is embedded in the pdf file.
+    ///
+    /// A font that is not embedded can not be used in a
+    /// [PDF/A](struct.Pdf.html#method.set_pdf_a) document.
+    /// The default implementation returns false, so an implementation
+    /// that embeds its font program should override it.
+    ///
+    /// # Examples
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// assert!(!BuiltinFont::Helvetica.is_embedded());
+    /// ```
+    fn is_embedded(&self) -> bool {
+        false
+    }
+
+    /// Get the builtin font that this font is, if it is one.
+    ///
+    /// This is used to give builtin fonts an
+    /// [automatic encoding](struct.Pdf.html#method.set_auto_encoding),
+    /// also when they are used through a `Box` or an `Arc`.
+    /// The default implementation returns None.
+    ///
+    /// # Examples
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// use std::sync::Arc;
+    /// let font = Arc::new(BuiltinFont::Courier);
+    /// assert_eq!(Some(BuiltinFont::Courier), font.as_builtin());
+    /// ```
+    fn as_builtin(&self) -> Option<BuiltinFont> {
+        None
+    }
+
     /// Get the width of a string in this font at given size.
     ///
     /// # Examples
@@ -78,27 +216,154 @@ pub trait FontSource: PartialEq + Eq + Hash {
     /// ```
     fn get_width_raw(&self, text: &str) -> u32;
 
+    /// Get the kerning adjustment between two characters, in thousands
+    /// of unit of text space.
+    /// A negative adjustment brings the characters closer.
+    ///
+    /// The default implementation has no kerning.
+    ///
+    /// # Examples
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// assert_eq!(-70, BuiltinFont::Helvetica.get_kerning('A', 'V'));
+    /// assert_eq!(0, BuiltinFont::Courier.get_kerning('A', 'V'));
+    /// ```
+    fn get_kerning(&self, _left: char, _right: char) -> i32 {
+        0
+    }
+
+    /// Get the width of a string in this font at given size, when
+    /// shown with kerning, as by
+    /// [TextObject::show_kerned](struct.TextObject.html#method.show_kerned).
+    ///
+    /// # Examples
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// let font = BuiltinFont::Helvetica;
+    /// assert_eq!(16.008, font.get_width(12.0, "AV"));
+    /// assert_eq!(15.168, font.get_kerned_width(12.0, "AV"));
+    /// ```
+    fn get_kerned_width(&self, size: f32, text: &str) -> f32 {
+        size * self.get_kerned_width_raw(text) as f32 / 1000.0
+    }
+
+    /// Get the width of a string in thousands of unit of text space,
+    /// when shown with kerning.
+    ///
+    /// # Examples
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource};
+    /// assert_eq!(1264, BuiltinFont::Helvetica.get_kerned_width_raw("AV"));
+    /// ```
+    fn get_kerned_width_raw(&self, text: &str) -> u32 {
+        let kerning = text
+            .chars()
+            .zip(text.chars().skip(1))
+            .map(|(left, right)| self.get_kerning(left, right))
+            .sum::<i32>();
+        (self.get_width_raw(text) as i32 + kerning).max(0) as u32
+    }
+
     /// Get the font metrics for font.
     fn get_metrics(&self) -> FontMetrics;
 }
 
+/// Implement FontSource for a pointer type, by forwarding all methods
+/// to the font pointed to.
+macro_rules! forward_font_source {
+    ($pointer:ident) => {
+        impl<F: FontSource + ?Sized> FontSource for $pointer<F> {
+            fn write_object(
+                &self,
+                pdf: &mut Pdf<dyn PdfOutput + '_>,
+                chars: &BTreeSet<char>,
+            ) -> Result<usize> {
+                (**self).write_object(pdf, chars)
+            }
+            fn pdf_name(&self) -> String {
+                (**self).pdf_name()
+            }
+            fn get_encoding(&self) -> &Encoding {
+                (**self).get_encoding()
+            }
+            fn pdf_string(&self, text: &str) -> Vec<u8> {
+                (**self).pdf_string(text)
+            }
+            fn can_show(&self, ch: char) -> bool {
+                (**self).can_show(ch)
+            }
+            fn is_embedded(&self) -> bool {
+                (**self).is_embedded()
+            }
+            fn as_builtin(&self) -> Option<BuiltinFont> {
+                (**self).as_builtin()
+            }
+            fn get_width(&self, size: f32, text: &str) -> f32 {
+                (**self).get_width(size, text)
+            }
+            fn get_width_raw(&self, text: &str) -> u32 {
+                (**self).get_width_raw(text)
+            }
+            fn get_kerning(&self, left: char, right: char) -> i32 {
+                (**self).get_kerning(left, right)
+            }
+            fn get_kerned_width(&self, size: f32, text: &str) -> f32 {
+                (**self).get_kerned_width(size, text)
+            }
+            fn get_kerned_width_raw(&self, text: &str) -> u32 {
+                (**self).get_kerned_width_raw(text)
+            }
+            fn get_metrics(&self) -> FontMetrics {
+                (**self).get_metrics()
+            }
+        }
+    };
+}
+
+forward_font_source!(Box);
+forward_font_source!(Arc);
+
+impl BuiltinFont {
+    /// Use this font with another encoding, typically created by
+    /// [Encoding::with_differences](struct.Encoding.html#method.with_differences).
+    ///
+    /// The builtin fonts have more glyphs than their default encoding
+    /// can show, so a custom encoding can show characters like Ł, ő or
+    /// š with a builtin font.
+    /// Glyphs that are not in the font are shown as nothing, or as a
+    /// substitute glyph, by the pdf viewer.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, FontSource, Pdf};
+    /// let encoding = BuiltinFont::Times_Roman.get_encoding()
+    ///     .with_differences(&[(128, "Lslash"), (129, "ohungarumlaut")]);
+    /// let font = BuiltinFont::Times_Roman.with_encoding(encoding);
+    /// assert_eq!(611, font.get_width_raw("Ł"));
+    ///
+    /// let mut document = Pdf::create("encoded_font.pdf").unwrap();
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     canvas.left_text(10.0, 200.0, font, 12.0, "Łeba, Győr, šuma")
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn with_encoding(self, encoding: Encoding) -> EncodedBuiltinFont {
+        let metrics = get_builtin_glyphs(self).metrics(&encoding);
+        EncodedBuiltinFont {
+            font: self,
+            encoding,
+            metrics,
+        }
+    }
+}
+
 impl FontSource for BuiltinFont {
-    fn write_object<F: std::io::Write + std::io::Read + std::io::Seek>(
+    fn write_object(
         &self,
-        pdf: &mut Pdf<F>,
-    ) -> io::Result<usize> {
-        // Note: This is enough for a Base14 font, other fonts will
-        // require a stream for the actual font, and probably another
-        // object for metrics etc
-        pdf.write_new_object(|font_object_id, pdf| {
-            writeln!(
-                pdf.output,
-                "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /{} >>",
-                self.pdf_name(),
-                self.get_encoding().get_name()
-            )?;
-            Ok(font_object_id)
-        })
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        chars: &BTreeSet<char>,
+    ) -> Result<usize> {
+        write_builtin_font(pdf, *self, self.get_encoding(), chars)
     }
 
     fn pdf_name(&self) -> String {
@@ -116,21 +381,137 @@ impl FontSource for BuiltinFont {
         }
     }
 
+    fn as_builtin(&self) -> Option<BuiltinFont> {
+        Some(*self)
+    }
+
     fn get_width(&self, size: f32, text: &str) -> f32 {
         size * self.get_width_raw(text) as f32 / 1000.0
     }
 
     fn get_width_raw(&self, text: &str) -> u32 {
-        let metrics = self.get_metrics();
-        self.get_encoding().encode_string(text).iter().fold(
-            0,
-            |result, &ch| {
-                result + u32::from(metrics.get_width(ch).unwrap_or(100))
-            },
-        )
+        width_raw(get_builtin_metrics(*self), self.get_encoding(), text)
+    }
+
+    fn get_kerning(&self, left: char, right: char) -> i32 {
+        let metrics = get_builtin_metrics(*self);
+        kerning(metrics, self.get_encoding(), left, right)
     }
 
     fn get_metrics(&self) -> FontMetrics {
         get_builtin_metrics(*self).clone()
     }
 }
+
+/// A builtin font with a custom encoding.
+///
+/// This is created by
+/// [BuiltinFont::with_encoding](enum.BuiltinFont.html#method.with_encoding).
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedBuiltinFont {
+    font: BuiltinFont,
+    encoding: Encoding,
+    metrics: FontMetrics,
+}
+
+impl FontSource for EncodedBuiltinFont {
+    fn write_object(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        chars: &BTreeSet<char>,
+    ) -> Result<usize> {
+        write_builtin_font(pdf, self.font, &self.encoding, chars)
+    }
+
+    /// The name is the name of the builtin font and a hash of the
+    /// encoding, so the same font can be used with several encodings
+    /// in a document.
+    fn pdf_name(&self) -> String {
+        let encoding = self.encoding.pdf_object();
+        let hash = fnv_hash(encoding.bytes());
+        format!("{}-{:016X}", self.font.pdf_name(), hash)
+    }
+
+    fn get_encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    fn get_width(&self, size: f32, text: &str) -> f32 {
+        size * self.get_width_raw(text) as f32 / 1000.0
+    }
+
+    fn get_width_raw(&self, text: &str) -> u32 {
+        width_raw(&self.metrics, &self.encoding, text)
+    }
+
+    fn get_kerning(&self, left: char, right: char) -> i32 {
+        kerning(&self.metrics, &self.encoding, left, right)
+    }
+
+    fn get_metrics(&self) -> FontMetrics {
+        self.metrics.clone()
+    }
+}
+
+/// Write the font dictionary for a builtin font with an encoding.
+fn write_builtin_font(
+    pdf: &mut Pdf<dyn PdfOutput + '_>,
+    font: BuiltinFont,
+    encoding: &Encoding,
+    chars: &BTreeSet<char>,
+) -> Result<usize> {
+    // Note: This is enough for a Base14 font, other fonts will
+    // require a stream for the actual font, and probably another
+    // object for metrics etc
+    let codes = simple_font_codes(encoding, chars);
+    let to_unicode_id = write_to_unicode(pdf, &codes, false)?;
+    pdf.write_new_object(|font_object_id, output| {
+        writeln!(
+            output,
+            "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding {}\n   \
+             /ToUnicode {} 0 R >>",
+            font.pdf_name(),
+            encoding.pdf_object(),
+            to_unicode_id,
+        )?;
+        Ok(font_object_id)
+    })
+}
+
+/// Get the width of `text` in a simple font with the given metrics.
+/// Characters that are not in the encoding can not be shown in the
+/// font, but are measured as a question mark.
+fn width_raw(metrics: &FontMetrics, encoding: &Encoding, text: &str) -> u32 {
+    text.chars().fold(0, |result, ch| {
+        let code = encoding.encode_char(ch).unwrap_or(b'?');
+        result + u32::from(metrics.get_width(code).unwrap_or(100))
+    })
+}
+
+/// Get the kerning between two characters in a simple font.
+fn kerning(
+    metrics: &FontMetrics,
+    encoding: &Encoding,
+    left: char,
+    right: char,
+) -> i32 {
+    let code = |ch| encoding.encode_char(ch).unwrap_or(b'?');
+    metrics.get_kerning(code(left), code(right))
+}
+
+#[test]
+fn test_encoded_font_name() {
+    let encoding = WIN_ANSI_ENCODING.with_differences(&[(128, "Lslash")]);
+    let font = BuiltinFont::Helvetica.with_encoding(encoding.clone());
+    // The name only depends on the encoding, on any platform.
+    assert_eq!("Helvetica-B55E246B9692D902", font.pdf_name());
+    assert_eq!(
+        font.pdf_name(),
+        BuiltinFont::Helvetica.with_encoding(encoding).pdf_name()
+    );
+    let other = WIN_ANSI_ENCODING.with_differences(&[(128, "lslash")]);
+    assert_ne!(
+        font.pdf_name(),
+        BuiltinFont::Helvetica.with_encoding(other).pdf_name()
+    );
+}
@@ -129,6 +129,10 @@ impl Matrix {
             v: [1., a.tan(), b.tan(), 1., 0., 0.],
         }
     }
+    /// Get the six numbers of the matrix.
+    pub(crate) fn values(&self) -> &[f32] {
+        &self.v
+    }
 }
 
 impl Display for Matrix {
@@ -0,0 +1,175 @@
+//! An sRGB ICC profile, for the output intent of PDF/A documents.
+//!
+//! The profile is a version 2 display profile, as PDF/A-1 needs, made
+//! from the colorants and transfer function of the sRGB standard, so
+//! no profile file needs to be shipped with this crate.
+
+/// The description of the profile, also used as the output condition
+/// of the output intent.
+pub(crate) const SRGB_DESCRIPTION: &str = "sRGB IEC61966-2.1";
+
+/// The number of entries in the tone reproduction curve.
+const CURVE_SIZE: usize = 1024;
+
+/// Create the sRGB ICC profile.
+pub(crate) fn srgb_profile() -> Vec<u8> {
+    // The red, green and blue colorants are adapted to D50, the
+    // illuminant of the profile connection space.
+    let tags: [(&[u8; 4], Vec<u8>); 9] = [
+        (b"desc", description(SRGB_DESCRIPTION)),
+        (b"cprt", text("No copyright, use freely")),
+        (b"wtpt", xyz([0.9505, 1.0, 1.0890])),
+        (b"rXYZ", xyz([0.4361, 0.2225, 0.0139])),
+        (b"gXYZ", xyz([0.3851, 0.7169, 0.0971])),
+        (b"bXYZ", xyz([0.1431, 0.0606, 0.7141])),
+        (b"rTRC", curve()),
+        (b"gTRC", curve()),
+        (b"bTRC", curve()),
+    ];
+    let mut table = Vec::new();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend_from_slice(*signature);
+        push_u32(&mut table, (data_start + data.len()) as u32);
+        push_u32(&mut table, tag.len() as u32);
+        data.extend_from_slice(tag);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+    }
+
+    let mut profile = Vec::with_capacity(data_start + data.len());
+    push_u32(&mut profile, (data_start + data.len()) as u32);
+    push_u32(&mut profile, 0); // preferred CMM
+    push_u32(&mut profile, 0x0210_0000); // version 2.1
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    for part in &[2020, 1, 1, 0, 0, 0] {
+        profile.extend_from_slice(&u16::to_be_bytes(*part));
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.resize(68, 0); // platform, flags, device and intent
+    profile.extend_from_slice(&xyz([0.9642, 1.0, 0.8249])[8..]);
+    profile.resize(128, 0); // creator and reserved
+    push_u32(&mut profile, tags.len() as u32);
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+/// A `textDescriptionType` tag, with only an ASCII description.
+fn description(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    push_u32(&mut tag, text.len() as u32 + 1);
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    // No Unicode and no ScriptCode description.
+    tag.resize(tag.len() + 4 + 4 + 2 + 1 + 67, 0);
+    tag
+}
+
+/// A `textType` tag.
+fn text(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+/// An `XYZType` tag, with one color.
+fn xyz(values: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in &values {
+        // An s15Fixed16Number.
+        push_u32(&mut tag, (value * 65536.0).round() as i32 as u32);
+    }
+    tag
+}
+
+/// A `curveType` tag, with the sRGB transfer function.
+fn curve() -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    push_u32(&mut tag, CURVE_SIZE as u32);
+    for i in 0..CURVE_SIZE {
+        let x = i as f64 / (CURVE_SIZE - 1) as f64;
+        let y = if x <= 0.04045 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        };
+        let y = (y * 65535.0).round() as u16;
+        tag.extend_from_slice(&y.to_be_bytes());
+    }
+    tag
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+#[test]
+fn test_srgb_profile() {
+    let profile = srgb_profile();
+    assert_eq!(
+        profile.len() as u32,
+        u32::from_be_bytes([profile[0], profile[1], profile[2], profile[3]])
+    );
+    assert_eq!(b"acsp", &profile[36..40]);
+    assert_eq!(&[0, 0, 0xF6, 0xD6], &profile[68..72]); // 0.9642
+    assert_eq!(&[0, 0, 0, 9], &profile[128..132]);
+    // The description tag comes first, after the tag table.
+    assert_eq!(b"desc", &profile[132..136]);
+    assert_eq!(b"desc", &profile[240..244]);
+    assert_eq!(b"sRGB IEC61966-2.1\0", &profile[252..270]);
+    assert_eq!(0, profile.len() % 4);
+}
+
+#[test]
+fn test_srgb_profile_tags() {
+    let profile = srgb_profile();
+    let u32_at = |pos: usize| {
+        u32::from_be_bytes([
+            profile[pos],
+            profile[pos + 1],
+            profile[pos + 2],
+            profile[pos + 3],
+        ]) as usize
+    };
+    let mut tags = std::collections::BTreeMap::new();
+    for i in 0..u32_at(128) {
+        let entry = 132 + 12 * i;
+        let (offset, size) = (u32_at(entry + 4), u32_at(entry + 8));
+        assert_eq!(0, offset % 4);
+        assert!(offset + size <= profile.len());
+        tags.insert(&profile[entry..entry + 4], offset);
+    }
+    // The required tags of a version 2 RGB display profile.
+    for tag in [
+        b"desc", b"cprt", b"wtpt", b"rXYZ", b"gXYZ", b"bXYZ", b"rTRC",
+        b"gTRC", b"bTRC",
+    ] {
+        assert!(tags.contains_key(&tag[..]));
+    }
+    // The colorants add up to the D50 white of the connection space.
+    let xyz = |tag: &[u8], i: usize| {
+        let pos = tags[tag] + 8 + 4 * i;
+        f64::from(u32_at(pos) as i32) / 65536.0
+    };
+    for (i, d50) in [0.9642, 1.0, 0.8249].iter().enumerate() {
+        let sum = xyz(b"rXYZ", i) + xyz(b"gXYZ", i) + xyz(b"bXYZ", i);
+        assert!((sum - d50).abs() < 5e-4, "{} != {}", sum, d50);
+    }
+    // The curve goes from black to white, and 50% is about 21.4%.
+    let curve = tags[&b"rTRC"[..]];
+    assert_eq!(CURVE_SIZE, u32_at(curve + 8));
+    let entry = |i: usize| {
+        u16::from_be_bytes([
+            profile[curve + 12 + 2 * i],
+            profile[curve + 13 + 2 * i],
+        ])
+    };
+    assert_eq!(0, entry(0));
+    assert_eq!(65535, entry(CURVE_SIZE - 1));
+    let half = f64::from(entry(CURVE_SIZE / 2)) / 65535.0;
+    assert!((half - 0.2140).abs() < 1e-3, "{}", half);
+}
@@ -0,0 +1,272 @@
+//! Raster images, written as image XObjects.
+use crate::{compress, Pdf, PdfError, PdfOutput, Result};
+use png::{BitDepth, ColorType, Transformations};
+use std::fmt;
+
+/// A reference to an image in a document.
+///
+/// An image is added to a document once, by
+/// [Pdf::add_jpeg](struct.Pdf.html#method.add_jpeg) or
+/// [Pdf::add_png](struct.Pdf.html#method.add_png), and can then be
+/// drawn any number of times, on any page, with
+/// [Canvas::draw_image](struct.Canvas.html#method.draw_image).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageRef {
+    id: usize,
+    width: u32,
+    height: u32,
+}
+
+impl ImageRef {
+    /// Get the object id of the image.
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the width of the image, in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Get the height of the image, in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl fmt::Display for ImageRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/Im{}", self.id)
+    }
+}
+
+/// Write a JPEG image, as it is, with the DCTDecode filter.
+pub(crate) fn write_jpeg(
+    pdf: &mut Pdf<dyn PdfOutput + '_>,
+    data: &[u8],
+) -> Result<ImageRef> {
+    let info = JpegInfo::parse(data)?;
+    let color_space = match info.components {
+        1 => "/DeviceGray",
+        3 => "/DeviceRGB",
+        4 => match pdf.pdf_a {
+            // The output intent of a PDF/A document is sRGB.
+            Some(pdf_a) => {
+                return Err(PdfError::Conformance(format!(
+                    "CMYK images are not allowed in {} documents with an \
+                     sRGB output intent",
+                    pdf_a
+                )))
+            }
+            None => "/DeviceCMYK",
+        },
+        n => {
+            return Err(invalid(&format!(
+                "Unsupported number of JPEG components: {}",
+                n
+            )))
+        }
+    };
+    // Adobe applications write CMYK JPEG files with inverted values.
+    let decode = if info.components == 4 && info.adobe {
+        " /Decode [ 1 0 1 0 1 0 1 0 ]"
+    } else {
+        ""
+    };
+    let (width, height) = (info.width.into(), info.height.into());
+    let dict = format!("{} /Filter /DCTDecode", decode);
+    let id = write_image(pdf, width, height, color_space, 8, &dict, data)?;
+    Ok(ImageRef { id, width, height })
+}
+
+/// Write a PNG image.
+///
+/// The image is decoded and written compressed with FlateDecode.
+/// Palette images and images with less than 8 bits per sample are
+/// expanded to 8 bits per sample, and an alpha channel or transparent
+/// color is written as a soft mask image.
+pub(crate) fn write_png(
+    pdf: &mut Pdf<dyn PdfOutput + '_>,
+    data: &[u8],
+) -> Result<ImageRef> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut pixels)?;
+    pixels.truncate(frame.buffer_size());
+    let (width, height) = (frame.width, frame.height);
+
+    let (color_space, colors, has_alpha) = match frame.color_type {
+        ColorType::Grayscale => ("/DeviceGray", 1, false),
+        ColorType::GrayscaleAlpha => ("/DeviceGray", 1, true),
+        ColorType::Rgb => ("/DeviceRGB", 3, false),
+        ColorType::Rgba => ("/DeviceRGB", 3, true),
+        ColorType::Indexed => return Err(invalid("Unexpanded PNG palette")),
+    };
+    let bits = match frame.bit_depth {
+        BitDepth::Sixteen => 16,
+        _ => 8,
+    };
+    let (color, alpha) = if has_alpha {
+        // Each pixel is the color samples followed by an alpha sample.
+        let sample = bits / 8;
+        let (mut color, mut alpha) = (Vec::new(), Vec::new());
+        for pixel in pixels.chunks(sample * (colors + 1)) {
+            let (c, a) = pixel.split_at(sample * colors);
+            color.extend_from_slice(c);
+            alpha.extend_from_slice(a);
+        }
+        (color, alpha)
+    } else {
+        (pixels, Vec::new())
+    };
+    // A completely opaque image needs no soft mask.
+    let dict = if alpha.iter().any(|&a| a != 0xFF) {
+        match pdf.pdf_a {
+            Some(pdf_a) if !pdf_a.allows_transparency() => {
+                return Err(PdfError::Conformance(format!(
+                    "Transparency is not allowed in {}, but the PNG image \
+                     is not opaque",
+                    pdf_a
+                )))
+            }
+            _ => (),
+        }
+        let alpha = compress(&alpha)?;
+        let smask = write_image(
+            pdf,
+            width,
+            height,
+            "/DeviceGray",
+            bits,
+            " /Filter /FlateDecode",
+            &alpha,
+        )?;
+        format!(" /SMask {} 0 R /Filter /FlateDecode", smask)
+    } else {
+        " /Filter /FlateDecode".to_string()
+    };
+    let color = compress(&color)?;
+    let id =
+        write_image(pdf, width, height, color_space, bits, &dict, &color)?;
+    Ok(ImageRef { id, width, height })
+}
+
+/// Write an image XObject, and return its object id.
+///
+/// The `dict` is any additional entries for the image dictionary,
+/// including a leading space.
+fn write_image(
+    pdf: &mut Pdf<dyn PdfOutput + '_>,
+    width: u32,
+    height: u32,
+    color_space: &str,
+    bits: usize,
+    dict: &str,
+    data: &[u8],
+) -> Result<usize> {
+    pdf.write_raw_stream(
+        &format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {}\n   \
+             /ColorSpace {} /BitsPerComponent {}{}",
+            width, height, color_space, bits, dict,
+        ),
+        data,
+    )
+}
+
+/// The properties of a JPEG image that are needed in a pdf file.
+struct JpegInfo {
+    width: u16,
+    height: u16,
+    components: u8,
+    /// True if the file has an Adobe APP14 marker.
+    adobe: bool,
+}
+
+impl JpegInfo {
+    /// Read the frame header (SOF marker) of a JPEG file.
+    fn parse(data: &[u8]) -> Result<JpegInfo> {
+        if !data.starts_with(&[0xFF, 0xD8]) {
+            return Err(invalid("Not a JPEG file"));
+        }
+        let mut adobe = false;
+        let mut pos = 2;
+        loop {
+            // A marker may be preceded by any number of fill bytes.
+            while data.get(pos + 1) == Some(&0xFF) {
+                pos += 1;
+            }
+            let marker = match data.get(pos..pos + 2) {
+                Some(&[0xFF, marker]) => marker,
+                _ => return Err(invalid("Bad marker in JPEG file")),
+            };
+            pos += 2;
+            match marker {
+                // Markers without a segment.
+                0x01 | 0xD0..=0xD7 => continue,
+                // End of image, or start of scan before any frame.
+                0xD9 | 0xDA => {
+                    return Err(invalid("No frame header in JPEG file"))
+                }
+                _ => (),
+            }
+            let length = data
+                .get(pos..pos + 2)
+                .map(|b| usize::from(u16::from_be_bytes([b[0], b[1]])))
+                .filter(|&length| length >= 2)
+                .ok_or_else(|| invalid("Bad segment in JPEG file"))?;
+            let segment = data
+                .get(pos + 2..pos + length)
+                .ok_or_else(|| invalid("Unexpected end of JPEG file"))?;
+            match marker {
+                // Start of frame, except DHT, JPG and DAC.
+                0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                    if segment.len() < 6 {
+                        return Err(invalid("Bad frame header in JPEG file"));
+                    }
+                    if segment[0] != 8 {
+                        return Err(invalid(&format!(
+                            "Unsupported JPEG precision: {} bits",
+                            segment[0]
+                        )));
+                    }
+                    let info = JpegInfo {
+                        height: u16::from_be_bytes([segment[1], segment[2]]),
+                        width: u16::from_be_bytes([segment[3], segment[4]]),
+                        components: segment[5],
+                        adobe,
+                    };
+                    if info.width == 0 || info.height == 0 {
+                        return Err(invalid("Unsupported JPEG image size"));
+                    }
+                    return Ok(info);
+                }
+                0xEE if segment.starts_with(b"Adobe") => adobe = true,
+                _ => (),
+            }
+            pos += length;
+        }
+    }
+}
+
+fn invalid(message: &str) -> PdfError {
+    PdfError::InvalidArgument(message.to_string())
+}
+
+#[test]
+fn test_jpeg_info() {
+    let mut data = vec![0xFF, 0xD8];
+    // An APP0 segment, a fill byte, and a progressive frame header.
+    data.extend_from_slice(&[0xFF, 0xE0, 0, 4, 0, 0]);
+    data.extend_from_slice(&[0xFF, 0xFF, 0xC2, 0, 11, 8, 0, 20, 1, 44, 3]);
+    data.extend_from_slice(&[1, 0x22, 0, 2, 0x11, 1]);
+    let info = JpegInfo::parse(&data).unwrap();
+    assert_eq!((300, 20, 3), (info.width, info.height, info.components));
+    assert!(!info.adobe);
+
+    let err = JpegInfo::parse(&data[..10]).err().unwrap();
+    assert_eq!("Invalid argument: Bad marker in JPEG file", err.to_string());
+    assert!(JpegInfo::parse(b"GIF89a").is_err());
+}
@@ -1,7 +1,8 @@
 //! A library for creating pdf files.
 //!
 //! Currently, simple vector graphics and text set in the 14 built-in
-//! fonts are supported.
+//! fonts or in embedded TrueType and Type1 fonts, and JPEG and PNG images,
+//! are supported.
 //! The main entry point of the crate is the [struct Pdf](struct.Pdf.html),
 //! representing a PDF file being written.
 
@@ -49,18 +50,34 @@
 #[macro_use]
 extern crate lazy_static;
 
-use chrono::offset::Local;
-use std::collections::{BTreeMap, HashMap};
+use chrono::offset::{Local, Offset, TimeZone, Utc};
+use chrono::{DateTime, FixedOffset};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, Cursor, SeekFrom, Write};
+use std::io::{self, Cursor, Write};
+use std::path::Path;
 
 mod fontsource;
-pub use crate::fontsource::{BuiltinFont, FontSource};
+pub use crate::fontsource::{BuiltinFont, EncodedBuiltinFont, FontSource};
 
 mod fontref;
 pub use crate::fontref::FontRef;
+use crate::fontref::FontRegistry;
 
+mod autoencoding;
+mod subset;
+mod tounicode;
+mod truetype;
+pub use crate::truetype::TrueTypeFont;
+
+mod type1;
+pub use crate::type1::Type1Font;
+
+mod afm;
 mod fontmetrics;
 pub use crate::fontmetrics::FontMetrics;
 
@@ -69,8 +86,28 @@ pub use crate::encoding::Encoding;
 
 pub mod graphicsstate;
 
+mod linearize;
 mod outline;
 use crate::outline::OutlineItem;
+mod textstring;
+use crate::textstring::text_string;
+
+mod xmp;
+use crate::xmp::{xmp_packet, CustomProperties};
+
+mod icc;
+mod pdfa;
+pub use crate::pdfa::PdfA;
+
+mod attachment;
+use crate::attachment::Attachment;
+pub use crate::attachment::Relationship;
+
+mod image;
+pub use crate::image::ImageRef;
+
+mod template;
+pub use crate::template::TemplateRef;
 
 mod canvas;
 pub use crate::canvas::Canvas;
@@ -78,6 +115,15 @@ pub use crate::canvas::Canvas;
 mod textobject;
 pub use crate::textobject::TextObject;
 
+mod error;
+use crate::error::check_finite;
+pub use crate::error::{PdfError, Result};
+
+#[cfg(feature = "tokio")]
+mod asyncpdf;
+#[cfg(feature = "tokio")]
+pub use crate::asyncpdf::AsyncPdf;
+
 /// The top-level object for writing a PDF.
 ///
 /// A PDF file is created with the `create` or `new` methods.
@@ -85,13 +131,134 @@ pub use crate::textobject::TextObject;
 /// are appended with the `render_page` method.
 /// Don't forget to call `finish` when done, to write the document
 /// trailer, without it the written file won't be a proper PDF.
-pub struct Pdf<F> {
-    output: F,
-    object_offsets: Vec<i64>,
-    page_objects_ids: Vec<usize>,
-    all_font_object_ids: HashMap<BuiltinFont, usize>,
+pub struct Pdf<F: ?Sized> {
+    objects: Vec<ObjectLocation>,
+    /// Objects waiting to be written in an object stream.
+    pending_objects: Vec<(usize, Vec<u8>)>,
+    /// All objects, for linearized output, by id.
+    held_objects: BTreeMap<usize, HeldObject>,
+    pages: Vec<Page>,
+    templates: Vec<Template>,
+    fonts: FontRegistry,
     outline_items: Vec<OutlineItem>,
     document_info: BTreeMap<String, String>,
+    creation_date: Option<DateTime<FixedOffset>>,
+    mod_date: Option<DateTime<FixedOffset>>,
+    xmp_properties: CustomProperties,
+    compress: bool,
+    object_streams: bool,
+    linearized: bool,
+    pdf_a: Option<PdfA>,
+    attachments: Vec<Attachment>,
+    // The output must be the last field, so that a `&mut Pdf<W>` can be
+    // coerced to a `&mut Pdf<dyn PdfOutput>`.
+    output: CountingWriter<F>,
+}
+
+/// A page that has been rendered, but not yet got its page dictionary
+/// written.
+///
+/// The page dictionaries are written when the document is finished,
+/// after the fonts, as fonts are not written until all pages are
+/// rendered.
+struct Page {
+    id: usize,
+    contents_id: usize,
+    width: f32,
+    height: f32,
+    fonts: Vec<FontRef>,
+    /// The XObjects drawn on the page, by name.
+    xobjects: BTreeMap<String, usize>,
+}
+
+/// A template that has been rendered, but not yet got its resource
+/// dictionary written.
+///
+/// Like for pages, the resources are written when the document is
+/// finished, after the fonts.
+struct Template {
+    resources_id: usize,
+    fonts: Vec<FontRef>,
+    /// The XObjects drawn in the template, by name.
+    xobjects: BTreeMap<String, usize>,
+}
+
+/// Where an object is in the output, for the cross-reference table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ObjectLocation {
+    /// The object id is reserved, but the object is not written yet.
+    Reserved,
+    /// The object is at a byte offset in the output.
+    Offset(u64),
+    /// The object is in the object stream with the given id, at the
+    /// given index.
+    Compressed(usize, usize),
+}
+
+/// An object that is kept in memory until the document is finished.
+struct HeldObject {
+    /// The object itself, or the dictionary of a stream.
+    content: Vec<u8>,
+    /// The data of a stream.
+    stream: Option<Vec<u8>>,
+}
+
+/// The maximum number of objects in an object stream.
+const OBJECTS_PER_STREAM: usize = 100;
+
+/// Any kind of output that a [Pdf](struct.Pdf.html) can be written to.
+///
+/// This is implemented for everything that is `Write`.
+/// It exists so that a [FontSource](trait.FontSource.html) can write
+/// its objects to a `Pdf` regardless of the type of its output.
+pub trait PdfOutput: Write {}
+
+impl<T: Write + ?Sized> PdfOutput for T {}
+
+/// A writer that counts the bytes written to it, so that the offsets
+/// of objects are known without seeking in the output, and hashes
+/// them for the file identifier.
+struct CountingWriter<W: ?Sized> {
+    count: u64,
+    hash: ContentHash,
+    // The inner writer must be the last field, see `Pdf`.
+    inner: W,
+}
+
+impl<W: Write + ?Sized> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        self.hash.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A 128-bit FNV-1a hash of the contents of a document, so that the
+/// file identifier is the same each time the same document is written.
+#[derive(Clone)]
+struct ContentHash(u128);
+
+impl ContentHash {
+    fn new() -> Self {
+        ContentHash(0x6c62_272e_07bb_0142_62b8_2175_6295_c58d)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= u128::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_0000_0000_0000_013b);
+        }
+    }
+
+    /// Get the `/ID` entry for a trailer, for a new file.
+    fn file_id(&self) -> String {
+        format!(" /ID [ <{:032X}> <{:032X}> ]", self.0, self.0)
+    }
 }
 
 const ROOT_OBJECT_ID: usize = 1;
@@ -99,54 +266,78 @@ const PAGES_OBJECT_ID: usize = 2;
 
 impl Pdf<File> {
     /// Create a new PDF document as a new file with given filename.
-    pub fn create(filename: &str) -> io::Result<Pdf<File>> {
+    pub fn create(filename: &str) -> Result<Pdf<File>> {
         let file = File::create(filename)?;
-        Pdf::<File>::new(file)
-    }
-
-    /// Create a new PDF document, writing to `output`.
-    pub fn new(mut output: File) -> io::Result<Pdf<File>> {
-        // TODO Maybe use a lower version?  Possibly decide by features used?
-        output.write_all(b"%PDF-1.7\n%\xB5\xED\xAE\xFB\n")?;
-        Ok(Pdf {
-            output,
-            // Object ID 0 is special in PDF.
-            // We reserve IDs 1 and 2 for the catalog and page tree.
-            object_offsets: vec![-1, -1, -1],
-            page_objects_ids: Vec::new(),
-            all_font_object_ids: HashMap::new(),
-            outline_items: Vec::new(),
-            document_info: BTreeMap::new(),
-        })
+        Pdf::new(file)
     }
 }
 
 impl Pdf<std::io::Cursor<Vec<u8>>> {
     /// Create a new PDF document as a buffer
-    pub fn create_with_buffer() -> io::Result<Self> {
-        let mut output = Cursor::new(Vec::new());
-
-        // TODO Maybe use a lower version?  Possibly decide by features used?
-        output.write_all(b"%PDF-1.7\n%\xB5\xED\xAE\xFB\n")?;
-        Ok(Pdf {
-            output,
-            // Object ID 0 is special in PDF.
-            // We reserve IDs 1 and 2 for the catalog and page tree.
-            object_offsets: vec![-1, -1, -1],
-            page_objects_ids: Vec::new(),
-            all_font_object_ids: HashMap::new(),
-            outline_items: Vec::new(),
-            document_info: BTreeMap::new(),
-        })
+    pub fn create_with_buffer() -> Result<Self> {
+        Pdf::new(Cursor::new(Vec::new()))
     }
 
     /// Export the PDF as raw bytes
     pub fn to_vec(self) -> Vec<u8> {
-        self.output.into_inner()
+        self.output.inner.into_inner()
     }
 }
 
-impl<W: std::io::Write + std::io::Read + std::io::Seek> Pdf<W> {
+impl<W: Write> Pdf<W> {
+    /// Create a new PDF document, writing to `output`.
+    ///
+    /// The output can be any writer.
+    /// Since a document is written in many small pieces, writing to a
+    /// file is much faster through a `BufWriter`.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, Pdf};
+    /// use std::fs::File;
+    /// use std::io::{BufWriter, Write};
+    ///
+    /// let file = BufWriter::new(File::create("buffered.pdf").unwrap());
+    /// let mut document = Pdf::new(file).unwrap();
+    /// document
+    ///     .render_page(180.0, 240.0, |canvas| {
+    ///         let font = BuiltinFont::Times_Roman;
+    ///         canvas.left_text(10.0, 200.0, font, 14.0, "Buffered")
+    ///     })
+    ///     .unwrap();
+    /// document.finish().unwrap().flush().unwrap();
+    /// ```
+    pub fn new(output: W) -> Result<Pdf<W>> {
+        let mut pdf = Pdf {
+            // Object ID 0 is special in PDF.
+            // We reserve IDs 1 and 2 for the catalog and page tree.
+            objects: vec![ObjectLocation::Reserved; 3],
+            pending_objects: Vec::new(),
+            held_objects: BTreeMap::new(),
+            pages: Vec::new(),
+            templates: Vec::new(),
+            fonts: FontRegistry::default(),
+            outline_items: Vec::new(),
+            document_info: BTreeMap::new(),
+            creation_date: None,
+            mod_date: None,
+            xmp_properties: CustomProperties::default(),
+            compress: false,
+            object_streams: false,
+            linearized: false,
+            pdf_a: None,
+            attachments: Vec::new(),
+            output: CountingWriter {
+                count: 0,
+                hash: ContentHash::new(),
+                inner: output,
+            },
+        };
+        // TODO Maybe use a lower version?  Possibly decide by features used?
+        pdf.output.write_all(b"%PDF-1.7\n%\xB5\xED\xAE\xFB\n")?;
+        Ok(pdf)
+    }
+
     /// Set metadata: the document's title.
     pub fn set_title(&mut self, title: &str) {
         self.document_info
@@ -180,10 +371,355 @@ impl<W: std::io::Write + std::io::Read + std::io::Seek> Pdf<W> {
         self.document_info
             .insert("Producer".to_string(), producer.to_string());
     }
+    /// Set metadata: the date and time the document was created.
+    ///
+    /// By default, this is the time given by the `SOURCE_DATE_EPOCH`
+    /// environment variable, if it is set, or else the time when the
+    /// document is finished.
+    /// A document with the same contents and dates is written the same
+    /// each time, including its file identifier.
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::{TimeZone, Utc};
+    /// use pdf_canvas::{BuiltinFont, Pdf};
+    ///
+    /// let write = || {
+    ///     let mut document = Pdf::create_with_buffer().unwrap();
+    ///     document.set_title("Reproducible");
+    ///     document.set_creation_date(Utc.timestamp_opt(0, 0).unwrap());
+    ///     document
+    ///         .render_page(180.0, 240.0, |canvas| {
+    ///             canvas.left_text(10.0, 200.0, BuiltinFont::Courier, 14.0,
+    ///                              "Same")
+    ///         })
+    ///         .unwrap();
+    ///     document.finish().unwrap().into_inner()
+    /// };
+    /// assert_eq!(write(), write());
+    /// ```
+    pub fn set_creation_date<Tz: TimeZone>(&mut self, date: DateTime<Tz>) {
+        self.creation_date = Some(date.with_timezone(&date.offset().fix()));
+    }
+    /// Set metadata: the date and time the document was last modified.
+    ///
+    /// By default, this is the creation date.
+    pub fn set_mod_date<Tz: TimeZone>(&mut self, date: DateTime<Tz>) {
+        self.mod_date = Some(date.with_timezone(&date.offset().fix()));
+    }
+    /// Set a custom property in the XMP metadata of the document.
+    ///
+    /// The document metadata is written both as a document info
+    /// dictionary and as an XMP metadata stream, where the title is
+    /// `dc:title`, the creation date is `xmp:CreateDate` and so on.
+    /// This adds a simple text property `name` in the namespace `uri`,
+    /// which is written with the given `prefix`, to the XMP metadata.
+    ///
+    /// The `prefix` and `name` must be XML names, and the namespaces
+    /// used for the standard properties can not be used.
+    ///
+    /// # Example
+    /// ```
+    /// # use pdf_canvas::Pdf;
+    /// let mut document = Pdf::create_with_buffer().unwrap();
+    /// document.set_title("Invoice");
+    /// let uri = "http://example.com/ns/invoice/";
+    /// document.set_xmp_property(uri, "inv", "Number", "2020-17").unwrap();
+    /// assert!(document.set_xmp_property(uri, "dc", "title", "").is_err());
+    /// ```
+    pub fn set_xmp_property(
+        &mut self,
+        uri: &str,
+        prefix: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        self.xmp_properties.set(uri, prefix, name, value)
+    }
+
+    /// Set if the builtin fonts should get encodings that are made for
+    /// the text shown in the document.
+    ///
+    /// By default, text in the builtin fonts is shown with
+    /// WinAnsiEncoding, and characters that are not in it can not be
+    /// shown.
+    /// With automatic encoding, any character that the fonts have a
+    /// glyph for can be shown, like Ł, ő or š.
+    /// Codes are given to such characters as they are shown, and if a
+    /// font needs more than 256 codes, it is written as several font
+    /// dictionaries.
+    ///
+    /// This applies to builtin fonts that are first used after the call.
+    /// Symbol and ZapfDingbats already have all their glyphs in their
+    /// encodings, so they are not changed.
+    /// The [FontRef](struct.FontRef.html) of an automatically encoded
+    /// font measures text with all glyphs in the font, while
+    /// `BuiltinFont::get_width` only knows WinAnsiEncoding.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, Pdf};
+    /// let mut document = Pdf::create("auto_encoding.pdf").unwrap();
+    /// document.set_auto_encoding(true);
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     let font = canvas.get_font(BuiltinFont::Helvetica);
+    ///     assert_eq!(6.672, font.get_width(12.0, "Ł"));
+    ///     canvas.left_text(10.0, 200.0, BuiltinFont::Helvetica, 12.0,
+    ///                      "Łódź, Győr, Šiauliai, Tórshavn")
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn set_auto_encoding(&mut self, auto_encoding: bool) {
+        self.fonts.set_auto_encoding(auto_encoding);
+    }
+
+    /// Set whether to compress streams with the FlateDecode filter.
+    ///
+    /// This applies to the content streams of pages and templates,
+    /// and to embedded font programs and ToUnicode maps, that are
+    /// written after the call.
+    /// Compression is off by default, which makes the content streams
+    /// easier to read and debug.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, Pdf};
+    /// let mut document = Pdf::create("compressed.pdf").unwrap();
+    /// document.set_compression(true);
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     canvas.left_text(10.0, 200.0, BuiltinFont::Helvetica, 12.0,
+    ///                      "Some text, that is deflated")
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn set_compression(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    /// Set whether to write objects in object streams, with a
+    /// cross-reference stream.
+    ///
+    /// Objects that are not streams themselves, like the font and page
+    /// dictionaries, are then packed in compressed object streams, and
+    /// the cross-reference table is written as a compressed stream too.
+    /// This makes the file smaller, but it can only be read by PDF 1.5
+    /// and later readers.
+    /// It should be set before any page is rendered.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, Pdf};
+    /// let mut document = Pdf::create("object_streams.pdf").unwrap();
+    /// document.set_compression(true);
+    /// document.set_object_streams(true);
+    /// for page in 1..=3 {
+    ///     document.render_page(180.0, 240.0, |canvas| {
+    ///         canvas.left_text(10.0, 200.0, BuiltinFont::Helvetica, 12.0,
+    ///                          &format!("Page {}", page))
+    ///     }).unwrap();
+    /// }
+    /// document.finish().unwrap();
+    /// ```
+    pub fn set_object_streams(&mut self, object_streams: bool) {
+        self.object_streams = object_streams;
+    }
+
+    /// Set whether to write the document linearized, for "fast web
+    /// view".
+    ///
+    /// A linearized document has the first page and everything needed
+    /// to show it at the start of the file, and hint tables to find the
+    /// other pages, so that a viewer can show a page before the whole
+    /// file is downloaded.
+    /// As the objects of the document are reordered, they are all kept
+    /// in memory until the document is finished.
+    /// Object streams are not used in linearized output, and a document
+    /// without pages is not linearized.
+    ///
+    /// This must be set before anything is added to the document.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{BuiltinFont, Pdf};
+    /// let mut document = Pdf::create("linearized.pdf").unwrap();
+    /// document.set_linearized(true).unwrap();
+    /// for page in 1..=3 {
+    ///     document.render_page(180.0, 240.0, |canvas| {
+    ///         canvas.left_text(10.0, 200.0, BuiltinFont::Helvetica, 12.0,
+    ///                          &format!("Page {}", page))
+    ///     }).unwrap();
+    /// }
+    /// document.finish().unwrap();
+    /// ```
+    pub fn set_linearized(&mut self, linearized: bool) -> Result<()> {
+        // Objects that are already written are not held for
+        // reordering, and held objects are only written when linearized.
+        if self.objects.len() > PAGES_OBJECT_ID + 1 {
+            return Err(PdfError::Structure(
+                "Linearization must be set before anything is added"
+                    .to_string(),
+            ));
+        }
+        self.linearized = linearized;
+        Ok(())
+    }
 
-    /// Return the current read/write position in the output file.
-    fn tell(&mut self) -> io::Result<u64> {
-        self.output.seek(SeekFrom::Current(0))
+    /// Make the document conform to a PDF/A level, for long-term
+    /// archiving.
+    ///
+    /// The document gets an sRGB output intent, and XMP metadata that
+    /// identifies the PDF/A level.
+    /// All fonts must be embedded, so text can not be shown in the
+    /// builtin fonts, but e.g. in a
+    /// [TrueTypeFont](struct.TrueTypeFont.html).
+    /// Content that the level does not allow is a
+    /// [conformance error](enum.PdfError.html#variant.Conformance),
+    /// such as a CMYK image, or, for PDF/A-1b, a PNG image with
+    /// transparency, or object streams.
+    ///
+    /// This must be set before anything is added to the document.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pdf_canvas::{Pdf, PdfA, TrueTypeFont};
+    /// let font = TrueTypeFont::from_file("fonts/DejaVuSans.ttf").unwrap();
+    /// let mut document = Pdf::create("archived.pdf").unwrap();
+    /// document.set_pdf_a(PdfA::A2b).unwrap();
+    /// document.set_title("Statement");
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     canvas.left_text(10.0, 200.0, font.clone(), 14.0, "Archived")
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn set_pdf_a(&mut self, level: PdfA) -> Result<()> {
+        // Only the catalog and the page tree are reserved initially.
+        // Attached files are only written when the document is
+        // finished, but were checked without the PDF/A level.
+        if self.objects.len() > PAGES_OBJECT_ID + 1
+            || !self.attachments.is_empty()
+        {
+            return Err(PdfError::Structure(
+                "PDF/A must be set before anything is added".to_string(),
+            ));
+        }
+        self.pdf_a = Some(level);
+        self.fonts.set_pdf_a(level);
+        Ok(())
+    }
+
+    /// Attach a file to the document.
+    ///
+    /// The file is embedded in the document with the given `name` and
+    /// `mime` type, e.g. `"text/xml"`, and listed with the attached
+    /// files of the document in a viewer.
+    /// The `relationship` tells how the file relates to the document.
+    /// The data is kept in memory until the document is finished.
+    ///
+    /// Any file can be attached in a PDF/A-3b document, only PDF/A
+    /// files in a PDF/A-2b document, and none in a PDF/A-1b document.
+    ///
+    /// # Example
+    /// An electronic invoice, with the XML of the invoice attached.
+    /// ```
+    /// use pdf_canvas::{Pdf, PdfA, Relationship};
+    /// let mut document = Pdf::create_with_buffer().unwrap();
+    /// document.set_pdf_a(PdfA::A3b).unwrap();
+    /// let xml = b"<rsm:CrossIndustryInvoice>...</rsm:CrossIndustryInvoice>";
+    /// document.attach_file("factur-x.xml", xml, "text/xml",
+    ///                      Relationship::Alternative).unwrap();
+    /// let fx = "urn:factur-x:pdfa:CrossIndustryDocument:invoice:1p0#";
+    /// document.set_xmp_property(fx, "fx", "DocumentFileName",
+    ///                           "factur-x.xml").unwrap();
+    /// document.set_xmp_property(fx, "fx", "DocumentType", "INVOICE")
+    ///     .unwrap();
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     canvas.rectangle(10.0, 10.0, 160.0, 220.0)?;
+    ///     canvas.stroke()
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn attach_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        mime: &str,
+        relationship: Relationship,
+    ) -> Result<()> {
+        if self.attachments.iter().any(|a| a.name() == name) {
+            return Err(PdfError::InvalidArgument(format!(
+                "A file named {:?} is already attached",
+                name
+            )));
+        }
+        let attachment =
+            Attachment::new(name, data, mime, relationship, self.pdf_a)?;
+        self.attachments.push(attachment);
+        Ok(())
+    }
+
+    /// Add a JPEG image to the document, to be drawn with
+    /// [Canvas::draw_image](struct.Canvas.html#method.draw_image).
+    ///
+    /// The image data is written to the document as it is, and the
+    /// size and color space of the image is read from its frame header.
+    /// Grayscale, RGB and CMYK images with 8 bits per component are
+    /// supported.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pdf_canvas::Pdf;
+    /// let mut document = Pdf::create("logo.pdf").unwrap();
+    /// let logo = document.add_jpeg_file("logo.jpg").unwrap();
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     let height = 160.0 * logo.height() as f32 / logo.width() as f32;
+    ///     canvas.draw_image(&logo, 10.0, 70.0, 160.0, height)
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn add_jpeg(&mut self, data: &[u8]) -> Result<ImageRef> {
+        image::write_jpeg(self, data)
+    }
+
+    /// Add a JPEG image from a file to the document.
+    ///
+    /// See [add_jpeg](#method.add_jpeg).
+    pub fn add_jpeg_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<ImageRef> {
+        self.add_jpeg(&std::fs::read(path)?)
+    }
+
+    /// Add a PNG image to the document, to be drawn with
+    /// [Canvas::draw_image](struct.Canvas.html#method.draw_image).
+    ///
+    /// Grayscale, RGB and palette images with 1 to 16 bits per sample
+    /// are supported, with or without transparency.
+    /// The image is decoded and written compressed, with any alpha
+    /// channel as a separate soft mask image.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pdf_canvas::Pdf;
+    /// let mut document = Pdf::create("signed.pdf").unwrap();
+    /// let signature = document.add_png_file("signature.png").unwrap();
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     canvas.draw_image(&signature, 10.0, 10.0, 120.0, 40.0)
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn add_png(&mut self, data: &[u8]) -> Result<ImageRef> {
+        image::write_png(self, data)
+    }
+
+    /// Add a PNG image from a file to the document.
+    ///
+    /// See [add_png](#method.add_png).
+    pub fn add_png_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<ImageRef> {
+        self.add_png(&std::fs::read(path)?)
     }
 
     /// Create a new page in the PDF document.
@@ -191,169 +727,272 @@ impl<W: std::io::Write + std::io::Read + std::io::Seek> Pdf<W> {
     /// The page will be `width` x `height` points large, and the
     /// actual content of the page will be created by the function
     /// `render_contents` by applying drawing methods on the Canvas.
+    ///
+    /// The size must be positive, and any state saved with
+    /// [Canvas::gsave](struct.Canvas.html#method.gsave) must be
+    /// restored when `render_contents` is done.
     pub fn render_page<F>(
         &mut self,
         width: f32,
         height: f32,
         render_contents: F,
-    ) -> io::Result<()>
+    ) -> Result<()>
     where
-        F: FnOnce(&mut Canvas) -> io::Result<()>,
+        F: FnOnce(&mut Canvas) -> Result<()>,
     {
-        let (contents_object_id, content_length, fonts, outline_items) = self
-            .write_new_object(move |contents_object_id, pdf| {
-                // Guess the ID of the next object. (We’ll assert it below.)
-                writeln!(
-                    pdf.output,
-                    "<< /Length {} 0 R >>\n\
-                     stream",
-                    contents_object_id + 1,
-                )?;
-
-                let start = pdf.tell()?;
-                writeln!(pdf.output, "/DeviceRGB cs /DeviceRGB CS")?;
-                let mut fonts = HashMap::new();
-                let mut outline_items = Vec::new();
-                render_contents(&mut Canvas::new(
-                    &mut pdf.output,
-                    &mut fonts,
-                    &mut outline_items,
-                ))?;
-                let end = pdf.tell()?;
-
-                writeln!(pdf.output, "endstream")?;
-                Ok((contents_object_id, end - start, fonts, outline_items))
-            })?;
-        self.write_new_object(|length_object_id, pdf| {
-            assert!(length_object_id == contents_object_id + 1);
-            writeln!(pdf.output, "{}", content_length)
-        })?;
-
-        let mut font_oids = NamedRefs::new(fonts.len());
-        for (src, r) in fonts {
-            if let Some(&object_id) = self.all_font_object_ids.get(&src) {
-                font_oids.insert(r, object_id);
-            } else {
-                let object_id = src.write_object(self)?;
-                font_oids.insert(r, object_id);
-                self.all_font_object_ids.insert(src, object_id);
-            }
+        check_finite(&[width, height])?;
+        if width <= 0.0 || height <= 0.0 {
+            return Err(PdfError::InvalidArgument(format!(
+                "Page size must be positive, got {} x {}",
+                width, height
+            )));
         }
-        let page_oid = self.write_page_dict(
-            contents_object_id,
+        if self.pending_objects.len() >= OBJECTS_PER_STREAM {
+            self.write_object_streams()?;
+        }
+        let mut contents = Vec::new();
+        let rendered = render_page_contents(
+            &mut contents,
+            &mut self.fonts,
+            render_contents,
+        );
+        let (outline_items, xobjects) = match rendered {
+            Ok(rendered) => rendered,
+            Err(err) => {
+                // Fonts used before the error are not used on any page.
+                self.fonts.take_page_fonts();
+                return Err(err);
+            }
+        };
+        let contents_object_id = self.write_stream("", &contents)?;
+
+        let page_oid = self.reserve_object_id();
+        self.pages.push(Page {
+            id: page_oid,
+            contents_id: contents_object_id,
             width,
             height,
-            font_oids,
-        )?;
+            fonts: self.fonts.take_page_fonts(),
+            xobjects,
+        });
         // Take the outline_items from this page, mark them with the page ref,
         // and save them for the document outline.
         for mut item in outline_items {
             item.set_page(page_oid);
             self.outline_items.push(item);
         }
-        self.page_objects_ids.push(page_oid);
         Ok(())
     }
 
+    /// Render a template, that can be drawn on any number of pages
+    /// with [Canvas::draw_template](struct.Canvas.html#method.draw_template).
+    ///
+    /// The template is written once, as a form XObject, and its
+    /// content is clipped to `bbox`, which is the lower left and the
+    /// upper right corners, as `[x1, y1, x2, y2]`.
+    /// Outline items added in a template are ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use pdf_canvas::{Pdf, BuiltinFont};
+    /// use pdf_canvas::graphicsstate::Matrix;
+    /// let mut document = Pdf::create("letterhead.pdf").unwrap();
+    /// let letterhead = document
+    ///     .render_template([0.0, 0.0, 180.0, 30.0], |canvas| {
+    ///         canvas.left_text(10.0, 10.0, BuiltinFont::Helvetica_Bold,
+    ///                          14.0, "ACME Inc.")
+    ///     })
+    ///     .unwrap();
+    /// for _ in 0..2 {
+    ///     document.render_page(180.0, 240.0, |canvas| {
+    ///         canvas.draw_template(&letterhead, Matrix::translate(0.0, 210.0))
+    ///     }).unwrap();
+    /// }
+    /// document.finish().unwrap();
+    /// ```
+    pub fn render_template<F>(
+        &mut self,
+        bbox: [f32; 4],
+        render_contents: F,
+    ) -> Result<TemplateRef>
+    where
+        F: FnOnce(&mut Canvas) -> Result<()>,
+    {
+        check_finite(&bbox)?;
+        let mut contents = Vec::new();
+        let mut outline_items = Vec::new();
+        let mut xobjects = BTreeMap::new();
+        let mut canvas = Canvas::new(
+            &mut contents,
+            &mut self.fonts,
+            &mut outline_items,
+            &mut xobjects,
+        );
+        let rendered = render_contents(&mut canvas)
+            .and_then(|()| canvas.check_restored());
+        if let Err(err) = rendered {
+            self.fonts.take_page_fonts();
+            return Err(err);
+        }
+        let resources_id = self.reserve_object_id();
+        let id = self.write_stream(
+            &format!(
+                "/Type /XObject /Subtype /Form\n   \
+                 /BBox [ {} {} {} {} ]\n   \
+                 /Resources {} 0 R",
+                bbox[0], bbox[1], bbox[2], bbox[3], resources_id,
+            ),
+            &contents,
+        )?;
+        self.templates.push(Template {
+            resources_id,
+            fonts: self.fonts.take_page_fonts(),
+            xobjects,
+        });
+        Ok(TemplateRef::new(id))
+    }
+
+    /// Write the objects for all fonts used in the document.
+    ///
+    /// Return the object id of each font, by index in the font registry.
+    /// Fonts that are only used on pages or templates that failed to
+    /// render are not written, and get no object id.
+    fn write_fonts(&mut self) -> Result<Vec<usize>> {
+        let fonts = std::mem::take(&mut self.fonts);
+        let used = self
+            .pages
+            .iter()
+            .flat_map(|page| &page.fonts)
+            .chain(self.templates.iter().flat_map(|t| &t.fonts))
+            .map(FontRef::index)
+            .collect::<BTreeSet<_>>();
+        // A font may be used on a page without showing any text in it.
+        for &n in &used {
+            fonts.check_font(&*fonts.fonts()[n].0)?;
+        }
+        let fonts = fonts.into_fonts();
+        let mut ids = vec![0; fonts.len()];
+        for n in used {
+            let (font, chars) = &fonts[n];
+            ids[n] = font.write_object(self, chars)?;
+        }
+        Ok(ids)
+    }
+
     fn write_page_dict(
         &mut self,
-        content_oid: usize,
-        width: f32,
-        height: f32,
-        font_oids: NamedRefs,
-    ) -> io::Result<usize> {
-        self.write_new_object(|page_oid, pdf| {
+        page: &Page,
+        resources: &str,
+    ) -> Result<()> {
+        self.write_object_with_id(page.id, |output| {
             writeln!(
-                pdf.output,
+                output,
                 "<< /Type /Page\n   \
                  /Parent {parent} 0 R\n   \
-                 /Resources << /Font << {fonts}>> >>\n   \
+                 /Resources {resources}\n   \
                  /MediaBox [ 0 0 {width} {height} ]\n   \
                  /Contents {c_oid} 0 R\n\
                  >>",
                 parent = PAGES_OBJECT_ID,
-                fonts = font_oids,
-                width = width,
-                height = height,
-                c_oid = content_oid,
+                resources = resources,
+                width = page.width,
+                height = page.height,
+                c_oid = page.contents_id,
             )
-            .map(|_| page_oid)
         })
     }
 
-    fn write_new_object<F, T>(&mut self, write_content: F) -> io::Result<T>
-    where
-        F: FnOnce(usize, &mut Pdf<W>) -> io::Result<T>,
-    {
-        let id = self.object_offsets.len();
-        let (result, offset) =
-            self.write_object(id, |pdf| write_content(id, pdf))?;
-        self.object_offsets.push(offset);
-        Ok(result)
-    }
-
-    fn write_object_with_id<F, T>(
-        &mut self,
-        id: usize,
-        write_content: F,
-    ) -> io::Result<T>
-    where
-        F: FnOnce(&mut Pdf<W>) -> io::Result<T>,
-    {
-        assert!(self.object_offsets[id] == -1);
-        let (result, offset) = self.write_object(id, write_content)?;
-        self.object_offsets[id] = offset;
-        Ok(result)
-    }
-
-    fn write_object<F, T>(
-        &mut self,
-        id: usize,
-        write_content: F,
-    ) -> io::Result<(T, i64)>
-    where
-        F: FnOnce(&mut Pdf<W>) -> io::Result<T>,
-    {
-        // `as i64` here would overflow for PDF files bigger than 2**63 bytes
-        let offset = self.tell()? as i64;
-        writeln!(self.output, "{} 0 obj", id)?;
-        let result = write_content(self)?;
-        writeln!(self.output, "endobj")?;
-        Ok((result, offset))
-    }
-
     /// Write out the document trailer.
     /// The trailer consists of the pages object, the root object,
     /// the xref list, the trailer object and the startxref position.
-    pub fn finish(mut self) -> io::Result<W> {
-        self.write_object_with_id(PAGES_OBJECT_ID, |pdf| {
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(pdf_a) = self.pdf_a {
+            if self.object_streams && !pdf_a.allows_object_streams() {
+                return Err(PdfError::Conformance(format!(
+                    "Object streams are not allowed in {}",
+                    pdf_a
+                )));
+            }
+        }
+        let font_ids = self.write_fonts()?;
+        for template in std::mem::take(&mut self.templates) {
+            let resources = resources_dict(
+                &template.fonts,
+                &template.xobjects,
+                &font_ids,
+            );
+            self.write_object_with_id(template.resources_id, |output| {
+                writeln!(output, "{}", resources)
+            })?;
+        }
+        let pages = std::mem::take(&mut self.pages);
+        for page in &pages {
+            let resources =
+                resources_dict(&page.fonts, &page.xobjects, &font_ids);
+            self.write_page_dict(page, &resources)?;
+        }
+        self.write_object_with_id(PAGES_OBJECT_ID, |output| {
             write!(
-                pdf.output,
+                output,
                 "<< /Type /Pages\n   \
                  /Count {}\n   ",
-                pdf.page_objects_ids.len()
+                pages.len()
             )?;
-            write!(pdf.output, "/Kids [ ")?;
-            for id in &pdf.page_objects_ids {
-                write!(pdf.output, "{} 0 R ", id)?;
+            write!(output, "/Kids [ ")?;
+            for page in &pages {
+                write!(output, "{} 0 R ", page.id)?;
             }
-            writeln!(pdf.output, "]\n>>")
+            writeln!(output, "]\n>>")
         })?;
-        let document_info_id = if !self.document_info.is_empty() {
+        let has_info = !self.document_info.is_empty()
+            || self.creation_date.is_some()
+            || self.mod_date.is_some()
+            || !self.xmp_properties.is_empty()
+            || self.pdf_a.is_some()
+            || !self.attachments.is_empty();
+        let mut metadata_id = None;
+        let mut attached_files = Vec::new();
+        let document_info_id = if has_info {
             let info = self.document_info.clone();
-            self.write_new_object(|page_object_id, pdf| {
-                write!(pdf.output, "<<")?;
+            let creation_date = match self.creation_date {
+                Some(date) => date,
+                None => default_date()?,
+            };
+            let mod_date = self.mod_date.unwrap_or(creation_date);
+            // Not compressed, so that tools that do not read pdf can
+            // find the metadata.
+            let xmp = xmp_packet(
+                &info,
+                &creation_date,
+                &mod_date,
+                &self.xmp_properties,
+                self.pdf_a,
+            );
+            metadata_id = Some(self.write_raw_stream(
+                "/Type /Metadata /Subtype /XML",
+                xmp.as_bytes(),
+            )?);
+            let attachments = std::mem::take(&mut self.attachments);
+            attached_files = attachment::write_attachments(
+                &mut self,
+                attachments,
+                &mod_date,
+            )?;
+            self.write_new_object(|page_object_id, output| {
+                write!(output, "<<")?;
                 for (key, value) in info {
-                    writeln!(pdf.output, " /{} ({})", key, value)?;
+                    write!(output, " /{} ", key)?;
+                    output.write_all(&text_string(&value))?;
+                    writeln!(output)?;
                 }
-                let now = Local::now().format("%Y%m%d%H%M%S%z").to_string();
                 write!(
-                    pdf.output,
-                    " /CreationDate (D:{now})\n \
-                     /ModDate (D:{now})",
-                    now = now,
+                    output,
+                    " /CreationDate ({})\n \
+                     /ModDate ({})",
+                    pdf_date(&creation_date),
+                    pdf_date(&mod_date),
                 )?;
-                writeln!(pdf.output, ">>")?;
+                writeln!(output, ">>")?;
                 Ok(Some(page_object_id))
             })?
         } else {
@@ -361,71 +1000,221 @@ impl<W: std::io::Write + std::io::Read + std::io::Seek> Pdf<W> {
         };
 
         let outlines_id = self.write_outlines()?;
+        let profile_id = match self.pdf_a {
+            Some(_) => Some(self.write_stream("/N 3", &icc::srgb_profile())?),
+            None => None,
+        };
 
-        self.write_object_with_id(ROOT_OBJECT_ID, |pdf| {
+        self.write_object_with_id(ROOT_OBJECT_ID, |output| {
             writeln!(
-                pdf.output,
+                output,
                 "<< /Type /Catalog\n   \
                  /Pages {} 0 R",
                 PAGES_OBJECT_ID,
             )?;
             if let Some(outlines_id) = outlines_id {
-                writeln!(pdf.output, "/Outlines {} 0 R", outlines_id)?;
+                writeln!(output, "/Outlines {} 0 R", outlines_id)?;
+            }
+            if let Some(metadata_id) = metadata_id {
+                writeln!(output, "/Metadata {} 0 R", metadata_id)?;
             }
-            writeln!(pdf.output, ">>")
+            if !attached_files.is_empty() {
+                write!(output, "/Names << /EmbeddedFiles << /Names [")?;
+                for (name, spec_id) in &attached_files {
+                    output.write_all(b" ")?;
+                    output.write_all(&text_string(name))?;
+                    write!(output, " {} 0 R", spec_id)?;
+                }
+                writeln!(output, " ] >> >>")?;
+                write!(output, "/AF [")?;
+                for (_, spec_id) in &attached_files {
+                    write!(output, " {} 0 R", spec_id)?;
+                }
+                writeln!(output, " ]")?;
+            }
+            if let Some(profile_id) = profile_id {
+                writeln!(
+                    output,
+                    "/OutputIntents [ << /Type /OutputIntent \
+                     /S /GTS_PDFA1\n   \
+                     /OutputConditionIdentifier ({condition})\n   \
+                     /Info ({condition})\n   \
+                     /RegistryName (http://www.color.org)\n   \
+                     /DestOutputProfile {id} 0 R >> ]",
+                    condition = icc::SRGB_DESCRIPTION,
+                    id = profile_id,
+                )?;
+            }
+            writeln!(output, ">>")
         })?;
-        let startxref = self.tell()?;
+        if self.linearized {
+            let objects = std::mem::take(&mut self.held_objects);
+            self.linearized = false;
+            if !pages.is_empty() {
+                let pages = pages
+                    .iter()
+                    .map(|page| (page.id, page.contents_id))
+                    .collect::<Vec<_>>();
+                // The objects are reordered, so hash them in id order.
+                let mut hash = self.output.hash.clone();
+                for object in objects.values() {
+                    hash.update(&object.content);
+                    hash.update(object.stream.as_deref().unwrap_or_default());
+                }
+                let start = self.tell();
+                linearize::write_linearized(
+                    &mut self.output,
+                    start,
+                    objects,
+                    &pages,
+                    document_info_id,
+                    &hash.file_id(),
+                )?;
+                return Ok(self.output.inner);
+            }
+            for (id, object) in objects {
+                self.put_object(
+                    id,
+                    object.content,
+                    object.stream.as_deref(),
+                )?;
+            }
+        }
+        self.write_object_streams()?;
+        let startxref = if self.object_streams {
+            self.write_xref_stream(document_info_id)?
+        } else {
+            self.write_xref_table(document_info_id)?
+        };
+        writeln!(
+            self.output,
+            "startxref\n\
+             {}\n\
+             %%EOF",
+            startxref,
+        )?;
+        Ok(self.output.inner)
+    }
+
+    /// Write the classic cross-reference table and trailer.
+    ///
+    /// Return the position of the table.
+    fn write_xref_table(
+        &mut self,
+        document_info_id: Option<usize>,
+    ) -> Result<u64> {
+        let startxref = self.tell();
         writeln!(
             self.output,
             "xref\n\
              0 {}\n\
              0000000000 65535 f ",
-            self.object_offsets.len(),
+            self.objects.len(),
         )?;
         // Object 0 (above) is special
-        // Use [1..] to skip object 0 in self.object_offsets.
-        for &offset in &self.object_offsets[1..] {
-            assert!(offset >= 0);
-            writeln!(self.output, "{:010} 00000 n ", offset)?;
+        // Use [1..] to skip object 0 in self.objects.
+        for location in &self.objects[1..] {
+            match location {
+                ObjectLocation::Offset(offset) => {
+                    writeln!(self.output, "{:010} 00000 n ", offset)?
+                }
+                _ => panic!("Object not written: {:?}", location),
+            }
         }
         writeln!(
             self.output,
             "trailer\n\
              << /Size {size}\n   \
              /Root {root} 0 R",
-            size = self.object_offsets.len(),
+            size = self.objects.len(),
             root = ROOT_OBJECT_ID,
         )?;
         if let Some(id) = document_info_id {
             writeln!(self.output, "   /Info {} 0 R", id)?;
         }
+        let file_id = self.output.hash.file_id();
+        writeln!(self.output, "  {}\n>>", file_id)?;
+        Ok(startxref)
+    }
+
+    /// Write a cross-reference stream, that is also the trailer.
+    ///
+    /// Return the position of the stream.
+    fn write_xref_stream(
+        &mut self,
+        document_info_id: Option<usize>,
+    ) -> Result<u64> {
+        let startxref = self.tell();
+        let id = self.objects.len();
+        self.objects.push(ObjectLocation::Offset(startxref));
+        // Each entry is a type, an offset or object stream id, and a
+        // generation number or index, with big-endian fields of these
+        // widths.
+        let max = self.objects.iter().fold(0, |max, location| {
+            max.max(match *location {
+                ObjectLocation::Offset(offset) => offset,
+                ObjectLocation::Compressed(stream_id, _) => stream_id as u64,
+                ObjectLocation::Reserved => 0,
+            })
+        });
+        let width = (1..8).find(|w| max >> (8 * w) == 0).unwrap_or(8);
+        let mut data = Vec::with_capacity(self.objects.len() * (width + 3));
+        for (i, location) in self.objects.iter().enumerate() {
+            let (kind, field2, field3) = match *location {
+                // Object 0 is the head of the list of free objects.
+                _ if i == 0 => (0, 0, 0xFFFF),
+                ObjectLocation::Offset(offset) => (1, offset, 0),
+                ObjectLocation::Compressed(stream_id, index) => {
+                    (2, stream_id as u64, index as u16)
+                }
+                ObjectLocation::Reserved => {
+                    panic!("Object not written: {}", i)
+                }
+            };
+            data.push(kind);
+            data.extend_from_slice(&field2.to_be_bytes()[8 - width..]);
+            data.extend_from_slice(&field3.to_be_bytes());
+        }
+        let info = document_info_id
+            .map(|id| format!(" /Info {} 0 R", id))
+            .unwrap_or_default();
+        let file_id = self.output.hash.file_id();
+        let data = compress(&data)?;
         writeln!(
             self.output,
-            ">>\n\
-             startxref\n\
-             {}\n\
-             %%EOF",
-            startxref,
+            "{id} 0 obj\n\
+             << /Type /XRef /Size {size} /W [ 1 {width} 2 ]\n   \
+             /Root {root} 0 R{info}{file_id}\n   \
+             /Filter /FlateDecode /Length {len} >>\n\
+             stream",
+            id = id,
+            size = self.objects.len(),
+            width = width,
+            root = ROOT_OBJECT_ID,
+            info = info,
+            file_id = file_id,
+            len = data.len(),
         )?;
-        Ok(self.output)
+        self.output.write_all(&data)?;
+        writeln!(self.output, "\nendstream\nendobj")?;
+        Ok(startxref)
     }
 
-    fn write_outlines(&mut self) -> io::Result<Option<usize>> {
+    fn write_outlines(&mut self) -> Result<Option<usize>> {
         if self.outline_items.is_empty() {
             return Ok(None);
         }
 
-        let parent_id = self.object_offsets.len();
-        self.object_offsets.push(-1);
+        let parent_id = self.reserve_object_id();
         let count = self.outline_items.len();
         let mut first_id = 0;
         let mut last_id = 0;
         let items = self.outline_items.clone();
         for (i, item) in items.iter().enumerate() {
             let (is_first, is_last) = (i == 0, i == count - 1);
-            let id = self.write_new_object(|object_id, pdf| {
+            let id = self.write_new_object(|object_id, output| {
                 item.write_dictionary(
-                    &mut pdf.output,
+                    output,
                     parent_id,
                     if is_first { None } else { Some(object_id - 1) },
                     if is_last { None } else { Some(object_id + 1) },
@@ -439,9 +1228,9 @@ impl<W: std::io::Write + std::io::Read + std::io::Seek> Pdf<W> {
                 last_id = id;
             }
         }
-        self.write_object_with_id(parent_id, |pdf| {
+        self.write_object_with_id(parent_id, |output| {
             writeln!(
-                pdf.output,
+                output,
                 "<< /Type /Outlines\n   \
                  /First {first} 0 R\n   \
                  /Last {last} 0 R\n   \
@@ -456,17 +1245,288 @@ impl<W: std::io::Write + std::io::Read + std::io::Seek> Pdf<W> {
     }
 }
 
+impl<W: PdfOutput + ?Sized> Pdf<W> {
+    /// Write a new object to the document, and return its object id.
+    ///
+    /// The `content` is the object itself, such as a dictionary, and is
+    /// written as it is.
+    /// This is intended for implementations of
+    /// [FontSource](trait.FontSource.html) outside of this crate, and
+    /// there should be no need to call it otherwise.
+    pub fn write_raw_object(&mut self, content: &[u8]) -> Result<usize> {
+        self.write_new_object(|id, output| {
+            output.extend_from_slice(content);
+            writeln!(output)?;
+            Ok(id)
+        })
+    }
+
+    /// Write a new stream object to the document, and return its
+    /// object id.
+    ///
+    /// The `dict` is the entries of the stream dictionary, except the
+    /// `/Length`, which is added.
+    /// This is intended for implementations of
+    /// [FontSource](trait.FontSource.html) outside of this crate, e.g.
+    /// for an embedded font program, and there should be no need to
+    /// call it otherwise.
+    pub fn write_raw_stream(
+        &mut self,
+        dict: &str,
+        data: &[u8],
+    ) -> Result<usize> {
+        let separator = if dict.is_empty() { "" } else { " " };
+        let id = self.reserve_object_id();
+        let dict =
+            format!("<< {}{}/Length {} >>\n", dict, separator, data.len());
+        self.put_object(id, dict.into_bytes(), Some(data))?;
+        Ok(id)
+    }
+
+    /// Write a new stream object to the document, and return its
+    /// object id.
+    ///
+    /// This is like [write_raw_stream](#method.write_raw_stream), except
+    /// that the data is compressed, with a `/Filter` added to the
+    /// `dict`, if compression is enabled with
+    /// [set_compression](#method.set_compression).
+    /// So the `dict` must not have a `/Filter` of its own.
+    pub fn write_stream(&mut self, dict: &str, data: &[u8]) -> Result<usize> {
+        if self.compress {
+            let dict = format!("{} /Filter /FlateDecode", dict);
+            self.write_raw_stream(dict.trim_start(), &compress(data)?)
+        } else {
+            self.write_raw_stream(dict, data)
+        }
+    }
+
+    /// Return the current position in the output, which is the number
+    /// of bytes written so far.
+    fn tell(&self) -> u64 {
+        self.output.count
+    }
+
+    /// Reserve an object id, for an object to be written later with
+    /// `write_object_with_id`.
+    fn reserve_object_id(&mut self) -> usize {
+        self.objects.push(ObjectLocation::Reserved);
+        self.objects.len() - 1
+    }
+
+    /// Write a new object, that is not a stream.
+    ///
+    /// The `write_content` closure gets the id of the object, and a
+    /// buffer to write the object to.
+    fn write_new_object<F, T>(&mut self, write_content: F) -> Result<T>
+    where
+        F: FnOnce(usize, &mut Vec<u8>) -> io::Result<T>,
+    {
+        let id = self.reserve_object_id();
+        self.write_object(id, |output| write_content(id, output))
+    }
+
+    fn write_object_with_id<F, T>(
+        &mut self,
+        id: usize,
+        write_content: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&mut Vec<u8>) -> io::Result<T>,
+    {
+        assert!(self.objects[id] == ObjectLocation::Reserved);
+        self.write_object(id, write_content)
+    }
+
+    /// Write an object that is not a stream, directly to the output or,
+    /// with object streams, to be written in an object stream later.
+    fn write_object<F, T>(&mut self, id: usize, write_content: F) -> Result<T>
+    where
+        F: FnOnce(&mut Vec<u8>) -> io::Result<T>,
+    {
+        let mut content = Vec::new();
+        let result = write_content(&mut content)?;
+        if self.object_streams && !self.linearized {
+            self.pending_objects.push((id, content));
+        } else {
+            self.put_object(id, content, None)?;
+        }
+        Ok(result)
+    }
+
+    /// Write an object directly to the output or, for linearized
+    /// output, keep it until the document is finished.
+    fn put_object(
+        &mut self,
+        id: usize,
+        content: Vec<u8>,
+        stream: Option<&[u8]>,
+    ) -> Result<()> {
+        if self.linearized {
+            let stream = stream.map(<[u8]>::to_vec);
+            self.held_objects.insert(id, HeldObject { content, stream });
+        } else {
+            self.objects[id] = ObjectLocation::Offset(self.tell());
+            write_indirect_object(&mut self.output, id, &content, stream)?;
+        }
+        Ok(())
+    }
+
+    /// Write the pending objects in compressed object streams.
+    ///
+    /// This allocates object ids for the streams, so it must not be
+    /// called between objects whose ids are expected to be consecutive.
+    fn write_object_streams(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending_objects);
+        for objects in pending.chunks(OBJECTS_PER_STREAM) {
+            // The stream starts with the id and offset of each object.
+            let mut offsets = String::new();
+            let mut contents = Vec::new();
+            for (id, content) in objects {
+                offsets.push_str(&format!("{} {} ", id, contents.len()));
+                contents.extend_from_slice(content);
+            }
+            offsets.push('\n');
+            let mut data = offsets.into_bytes();
+            let first = data.len();
+            data.extend_from_slice(&contents);
+            let stream_id = self.write_raw_stream(
+                &format!(
+                    "/Type /ObjStm /N {} /First {} /Filter /FlateDecode",
+                    objects.len(),
+                    first,
+                ),
+                &compress(&data)?,
+            )?;
+            for (index, (id, _)) in objects.iter().enumerate() {
+                self.objects[*id] =
+                    ObjectLocation::Compressed(stream_id, index);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Render the contents of a page to `output`.
+///
+/// Return the outline items and XObjects of the page.
+fn render_page_contents<F>(
+    output: &mut dyn Write,
+    fonts: &mut FontRegistry,
+    render_contents: F,
+) -> Result<(Vec<OutlineItem>, BTreeMap<String, usize>)>
+where
+    F: FnOnce(&mut Canvas) -> Result<()>,
+{
+    writeln!(output, "/DeviceRGB cs /DeviceRGB CS")?;
+    let mut outline_items = Vec::new();
+    let mut xobjects = BTreeMap::new();
+    let mut canvas =
+        Canvas::new(output, fonts, &mut outline_items, &mut xobjects);
+    render_contents(&mut canvas)?;
+    canvas.check_restored()?;
+    Ok((outline_items, xobjects))
+}
+
+/// Write an indirect object, with the given content, and data if the
+/// object is a stream.
+fn write_indirect_object<O: Write + ?Sized>(
+    output: &mut O,
+    id: usize,
+    content: &[u8],
+    stream: Option<&[u8]>,
+) -> io::Result<()> {
+    writeln!(output, "{} 0 obj", id)?;
+    output.write_all(content)?;
+    if let Some(data) = stream {
+        writeln!(output, "stream")?;
+        output.write_all(data)?;
+        writeln!(output, "\nendstream")?;
+    }
+    writeln!(output, "endobj")
+}
+
+/// Get the default creation date of a document, from the
+/// `SOURCE_DATE_EPOCH` environment variable if it is set, or else the
+/// current time.
+fn default_date() -> Result<DateTime<FixedOffset>> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => parse_source_date_epoch(&value),
+        Err(_) => Ok(Local::now().into()),
+    }
+}
+
+/// Parse a `SOURCE_DATE_EPOCH`, a number of seconds since 1970 UTC.
+fn parse_source_date_epoch(value: &str) -> Result<DateTime<FixedOffset>> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+        .map(DateTime::into)
+        .ok_or_else(|| {
+            PdfError::InvalidArgument(format!(
+                "Bad SOURCE_DATE_EPOCH {:?}",
+                value
+            ))
+        })
+}
+
+/// Format a date as a pdf date string, without the delimiters.
+fn pdf_date(date: &DateTime<FixedOffset>) -> String {
+    let offset = date.offset().local_minus_utc();
+    let minutes = offset.abs() / 60;
+    format!(
+        "D:{}{}{:02}'{:02}'",
+        date.format("%Y%m%d%H%M%S"),
+        if offset < 0 { '-' } else { '+' },
+        minutes / 60,
+        minutes % 60,
+    )
+}
+
+/// Compress data with the zlib format of FlateDecode.
+fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Get the resource dictionary for a page or template, given the
+/// object id of each font by index in the font registry.
+fn resources_dict(
+    fonts: &[FontRef],
+    xobjects: &BTreeMap<String, usize>,
+    font_ids: &[usize],
+) -> String {
+    let mut font_oids = NamedRefs::new();
+    for font in fonts {
+        font_oids.insert(font.to_string(), font_ids[font.index()]);
+    }
+    let mut result = format!("<< /Font << {}>>", font_oids);
+    if !xobjects.is_empty() {
+        result.push_str(" /XObject << ");
+        for (name, id) in xobjects {
+            result.push_str(&format!("{} {} 0 R ", name, id));
+        }
+        result.push_str(">>");
+    }
+    result.push_str(" >>");
+    result
+}
+
+/// Named references in a resource dictionary, sorted by name so that
+/// the output is the same each time.
 struct NamedRefs {
-    oids: HashMap<FontRef, usize>,
+    oids: BTreeMap<String, usize>,
 }
 
 impl NamedRefs {
-    fn new(capacity: usize) -> Self {
+    fn new() -> Self {
         NamedRefs {
-            oids: HashMap::with_capacity(capacity),
+            oids: BTreeMap::new(),
         }
     }
-    fn insert(&mut self, name: FontRef, oid: usize) -> Option<usize> {
+    fn insert(&mut self, name: String, oid: usize) -> Option<usize> {
         self.oids.insert(name, oid)
     }
 }
@@ -479,3 +1539,15 @@ impl fmt::Display for NamedRefs {
         Ok(())
     }
 }
+
+#[test]
+fn test_source_date_epoch() {
+    let date = parse_source_date_epoch("1234567890\n").unwrap();
+    assert_eq!("D:20090213233130+00'00'", pdf_date(&date));
+    assert!(parse_source_date_epoch("yesterday").is_err());
+    let east = FixedOffset::east_opt(3600 + 45 * 60).unwrap();
+    assert_eq!(
+        "D:20090214011630+01'45'",
+        pdf_date(&date.with_timezone(&east))
+    );
+}
@@ -0,0 +1,627 @@
+//! Linearized output, where the first page, and hint tables for finding
+//! the other pages, are at the start of the file.
+//!
+//! The objects of the document are reordered, and renumbered so that
+//! each cross-reference section is a single range, like this:
+//!
+//! 1. The linearization dictionary, and the cross-reference table
+//!    and trailer for the first page.
+//! 2. The catalog, and the hint stream.
+//! 3. The first page, and all objects it needs.
+//! 4. The other pages, each with the objects only it needs.
+//! 5. The objects shared by several pages, that are not needed by the
+//!    first page.
+//! 6. All other objects, like the page tree and the outline.
+//! 7. The main cross-reference table, for parts 4 to 6.
+use crate::{
+    write_indirect_object, HeldObject, PAGES_OBJECT_ID, ROOT_OBJECT_ID,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// The size of the linearization dictionary object and of the first
+/// page trailer, which are padded so that they can be written before
+/// the offsets in them are known.
+const LINEARIZATION_SIZE: usize = 200;
+const TRAILER_SIZE: usize = 200;
+
+/// Write a linearized document, with the objects at `start` in the
+/// output.
+///
+/// The `pages` are the object ids of each page and its contents, and
+/// `file_id` is the `/ID` entry for the first page trailer.
+pub(crate) fn write_linearized<O: Write + ?Sized>(
+    output: &mut O,
+    start: u64,
+    objects: BTreeMap<usize, HeldObject>,
+    pages: &[(usize, usize)],
+    info_id: Option<usize>,
+    file_id: &str,
+) -> io::Result<()> {
+    let references = objects
+        .iter()
+        .map(|(&id, object)| {
+            let ids = references(&object.content);
+            (id, ids.into_iter().map(|(_, id)| id).collect())
+        })
+        .collect::<BTreeMap<usize, Vec<usize>>>();
+    let page_ids = pages.iter().map(|&(id, _)| id).collect::<BTreeSet<_>>();
+    // The objects needed to show each page.
+    let needed = pages
+        .iter()
+        .map(|&(page_id, _)| {
+            needed_objects(page_id, &references, |id| {
+                id == PAGES_OBJECT_ID
+                    || (id != page_id && page_ids.contains(&id))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut users = BTreeMap::<usize, Vec<usize>>::new();
+    for (page, ids) in needed.iter().enumerate().skip(1) {
+        for &id in ids.difference(&needed[0]) {
+            users.entry(id).or_default().push(page);
+        }
+    }
+    let first_page = page_section(pages[0], &needed[0]);
+    let other_pages = (1..pages.len())
+        .map(|page| {
+            let own = needed[page]
+                .iter()
+                .filter(|id| users.get(id) == Some(&vec![page]))
+                .cloned()
+                .collect();
+            page_section(pages[page], &own)
+        })
+        .collect::<Vec<_>>();
+    let shared = users
+        .iter()
+        .filter(|(_, pages)| pages.len() > 1)
+        .map(|(&id, _)| id)
+        .collect::<Vec<_>>();
+    let placed = first_page
+        .iter()
+        .chain(other_pages.iter().flatten())
+        .chain(&shared)
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let other = objects
+        .keys()
+        .filter(|&&id| id != ROOT_OBJECT_ID && !placed.contains(&id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // The main cross-reference section is objects 0 to main_size - 1,
+    // and the first page section follows it.
+    let main_order = other_pages
+        .iter()
+        .flatten()
+        .chain(&shared)
+        .chain(&other)
+        .cloned()
+        .collect::<Vec<_>>();
+    let main_size = main_order.len() + 1;
+    let linearization_id = main_size;
+    let hint_id = main_size + 2;
+    let mut new_ids = BTreeMap::new();
+    for (i, &id) in main_order.iter().enumerate() {
+        new_ids.insert(id, i + 1);
+    }
+    new_ids.insert(ROOT_OBJECT_ID, main_size + 1);
+    for (i, &id) in first_page.iter().enumerate() {
+        new_ids.insert(id, main_size + 3 + i);
+    }
+    let size = main_size + 3 + first_page.len();
+
+    let serialize = |id: usize| -> io::Result<Vec<u8>> {
+        let object = &objects[&id];
+        let content = renumber(&object.content, &new_ids);
+        let mut result = Vec::new();
+        write_indirect_object(
+            &mut result,
+            new_ids[&id],
+            &content,
+            object.stream.as_deref(),
+        )?;
+        Ok(result)
+    };
+    let catalog = serialize(ROOT_OBJECT_ID)?;
+    let first_page_ids = first_page;
+    let first_page = first_page_ids
+        .iter()
+        .map(|&id| Ok((id, serialize(id)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let main = main_order
+        .iter()
+        .map(|&id| Ok((id, serialize(id)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // The offsets of everything after the hint stream, as if there were
+    // no hint stream, as used in the hint tables.
+    let first_xref_size =
+        format!("xref\n{} {}\n", main_size, size - main_size).len()
+            + 20 * (size - main_size)
+            + TRAILER_SIZE
+            + "startxref\n0\n%%EOF\n".len();
+    let catalog_offset =
+        start + (LINEARIZATION_SIZE + first_xref_size) as u64;
+    let hint_offset = catalog_offset + catalog.len() as u64;
+    let mut offsets = BTreeMap::new();
+    let mut offset = hint_offset;
+    for (id, bytes) in first_page.iter().chain(&main) {
+        offsets.insert(*id, offset);
+        offset += bytes.len() as u64;
+    }
+    let lengths = first_page
+        .iter()
+        .chain(&main)
+        .map(|(id, bytes)| (*id, bytes.len() as u64))
+        .collect::<BTreeMap<_, _>>();
+
+    let hints = Hints {
+        offsets: &offsets,
+        lengths: &lengths,
+        new_ids: &new_ids,
+    };
+    let page_table = hints.page_offset_table(
+        pages,
+        &first_page_ids,
+        &other_pages,
+        &shared,
+        &needed,
+    );
+    let shared_table = hints.shared_object_table(&first_page_ids, &shared);
+    let mut hint_data = page_table;
+    let shared_table_offset = hint_data.len();
+    hint_data.extend_from_slice(&shared_table);
+    let mut hint_stream = Vec::new();
+    write_indirect_object(
+        &mut hint_stream,
+        hint_id,
+        format!(
+            "<< /S {} /Length {} >>\n",
+            shared_table_offset,
+            hint_data.len()
+        )
+        .as_bytes(),
+        Some(&hint_data),
+    )?;
+    let hint_length = hint_stream.len() as u64;
+
+    // The real offsets, and the layout of the end of the file.
+    let first_page_end = offsets[&first_page[0].0]
+        + first_page.iter().map(|(_, b)| b.len() as u64).sum::<u64>()
+        + hint_length;
+    let main_xref_offset = offset + hint_length;
+    let main_xref_head = format!("xref\n0 {}", main_size);
+    let main_xref_size = main_xref_head.len()
+        + "\n0000000000 65535 f \n".len()
+        + 20 * main_order.len();
+    let main_trailer = format!(
+        "trailer\n<< /Size {} >>\nstartxref\n{}\n%%EOF\n",
+        main_size,
+        start + LINEARIZATION_SIZE as u64,
+    );
+    let file_length =
+        main_xref_offset + (main_xref_size + main_trailer.len()) as u64;
+
+    let linearization = format!(
+        "{} 0 obj\n\
+         << /Linearized 1 /L {} /H [ {} {} ] /O {} /E {} /N {} /T {} >>",
+        linearization_id,
+        file_length,
+        hint_offset,
+        hint_length,
+        new_ids[&pages[0].0],
+        first_page_end,
+        pages.len(),
+        main_xref_offset + main_xref_head.len() as u64,
+    );
+    output.write_all(&padded(
+        linearization,
+        LINEARIZATION_SIZE,
+        "\nendobj\n",
+    ))?;
+
+    write!(output, "xref\n{} {}\n", main_size, size - main_size)?;
+    writeln!(output, "{:010} 00000 n ", start)?;
+    writeln!(output, "{:010} 00000 n ", catalog_offset)?;
+    writeln!(output, "{:010} 00000 n ", hint_offset)?;
+    for (id, _) in &first_page {
+        writeln!(output, "{:010} 00000 n ", offsets[id] + hint_length)?;
+    }
+    let info = info_id
+        .map(|id| format!(" /Info {} 0 R", new_ids[&id]))
+        .unwrap_or_default();
+    let trailer = format!(
+        "trailer\n<< /Size {} /Prev {} /Root {} 0 R{}{} >>",
+        size, main_xref_offset, new_ids[&ROOT_OBJECT_ID], info, file_id,
+    );
+    output.write_all(&padded(trailer, TRAILER_SIZE, "\n"))?;
+    write!(output, "startxref\n0\n%%EOF\n")?;
+
+    output.write_all(&catalog)?;
+    output.write_all(&hint_stream)?;
+    for (_, bytes) in first_page.iter().chain(&main) {
+        output.write_all(bytes)?;
+    }
+
+    write!(output, "{}\n0000000000 65535 f \n", main_xref_head)?;
+    for id in &main_order {
+        writeln!(output, "{:010} 00000 n ", offsets[id] + hint_length)?;
+    }
+    output.write_all(main_trailer.as_bytes())
+}
+
+/// Get the objects in the section of a page, the page object and its
+/// contents first.
+fn page_section(
+    (page_id, contents_id): (usize, usize),
+    ids: &BTreeSet<usize>,
+) -> Vec<usize> {
+    let mut result = vec![page_id, contents_id];
+    result.extend(
+        ids.iter()
+            .filter(|&&id| id != page_id && id != contents_id)
+            .cloned(),
+    );
+    result
+}
+
+/// Get all objects that are referenced, directly or indirectly, by
+/// the object `id`, including itself, except those that `stop` is
+/// true for.
+fn needed_objects<S>(
+    id: usize,
+    references: &BTreeMap<usize, Vec<usize>>,
+    stop: S,
+) -> BTreeSet<usize>
+where
+    S: Fn(usize) -> bool,
+{
+    let mut result = BTreeSet::new();
+    let mut todo = vec![id];
+    while let Some(id) = todo.pop() {
+        if let Some(ids) = references.get(&id) {
+            if result.insert(id) {
+                todo.extend(ids.iter().filter(|&&id| !stop(id)));
+            }
+        }
+    }
+    result
+}
+
+/// Pad a string with spaces, before the `end`, to `size` bytes.
+fn padded(mut text: String, size: usize, end: &str) -> Vec<u8> {
+    assert!(text.len() + end.len() <= size, "Too long: {}", text);
+    while text.len() + end.len() < size {
+        text.push(' ');
+    }
+    text.push_str(end);
+    text.into_bytes()
+}
+
+/// The hint tables, with offsets and lengths of the objects as if
+/// there were no hint stream.
+struct Hints<'a> {
+    offsets: &'a BTreeMap<usize, u64>,
+    lengths: &'a BTreeMap<usize, u64>,
+    new_ids: &'a BTreeMap<usize, usize>,
+}
+
+impl Hints<'_> {
+    /// Get the page offset hint table.
+    fn page_offset_table(
+        &self,
+        pages: &[(usize, usize)],
+        first_page: &[usize],
+        other_pages: &[Vec<usize>],
+        shared: &[usize],
+        needed: &[BTreeSet<usize>],
+    ) -> Vec<u8> {
+        let sections = Some(first_page)
+            .into_iter()
+            .chain(other_pages.iter().map(Vec::as_slice))
+            .collect::<Vec<_>>();
+        let n_objects = sections
+            .iter()
+            .map(|section| section.len() as u64)
+            .collect::<Vec<_>>();
+        let lengths = sections
+            .iter()
+            .map(|section| section.iter().map(|id| self.lengths[id]).sum())
+            .collect::<Vec<u64>>();
+        let content_offsets = pages
+            .iter()
+            .map(|(page, contents)| {
+                self.offsets[contents] - self.offsets[page]
+            })
+            .collect::<Vec<_>>();
+        let content_lengths = pages
+            .iter()
+            .map(|(_, contents)| self.lengths[contents])
+            .collect::<Vec<_>>();
+        // The shared objects of each page are identified by their index
+        // in the shared object hint table, that has the objects of the
+        // first page first.
+        let groups = first_page
+            .iter()
+            .chain(shared)
+            .enumerate()
+            .map(|(i, &id)| (id, i as u64))
+            .collect::<BTreeMap<_, _>>();
+        let page_groups = needed
+            .iter()
+            .enumerate()
+            .map(|(page, ids)| {
+                if page == 0 {
+                    return Vec::new();
+                }
+                let mut ids = ids
+                    .iter()
+                    .filter_map(|id| groups.get(id).cloned())
+                    .collect::<Vec<_>>();
+                ids.sort_unstable();
+                ids
+            })
+            .collect::<Vec<_>>();
+        let n_groups = page_groups
+            .iter()
+            .map(|groups| groups.len() as u64)
+            .collect::<Vec<_>>();
+        let max_group = page_groups.iter().flatten().max().cloned();
+
+        let mut table = BitWriter::default();
+        let (min_objects, objects_bits) = range_bits(&n_objects);
+        let (min_length, length_bits) = range_bits(&lengths);
+        let (min_content_offset, content_offset_bits) =
+            range_bits(&content_offsets);
+        let (min_content_length, content_length_bits) =
+            range_bits(&content_lengths);
+        let groups_bits = bits(n_groups.iter().max().cloned().unwrap_or(0));
+        let group_bits = bits(max_group.unwrap_or(0));
+        table.write(min_objects, 32);
+        table.write(self.offsets[&pages[0].0], 32);
+        table.write(objects_bits.into(), 16);
+        table.write(min_length, 32);
+        table.write(length_bits.into(), 16);
+        table.write(min_content_offset, 32);
+        table.write(content_offset_bits.into(), 16);
+        table.write(min_content_length, 32);
+        table.write(content_length_bits.into(), 16);
+        table.write(groups_bits.into(), 16);
+        table.write(group_bits.into(), 16);
+        // No fractional positions of shared objects.
+        table.write(0, 16);
+        table.write(0, 16);
+        table.write_all(&n_objects, min_objects, objects_bits);
+        table.write_all(&lengths, min_length, length_bits);
+        table.write_all(&n_groups, 0, groups_bits);
+        let all_groups = page_groups.concat();
+        table.write_all(&all_groups, 0, group_bits);
+        table.write_all(
+            &content_offsets,
+            min_content_offset,
+            content_offset_bits,
+        );
+        table.write_all(
+            &content_lengths,
+            min_content_length,
+            content_length_bits,
+        );
+        table.into_bytes()
+    }
+
+    /// Get the shared object hint table, where each object of the first
+    /// page and each shared object is a group of its own.
+    fn shared_object_table(
+        &self,
+        first_page: &[usize],
+        shared: &[usize],
+    ) -> Vec<u8> {
+        let lengths = first_page
+            .iter()
+            .chain(shared)
+            .map(|id| self.lengths[id])
+            .collect::<Vec<_>>();
+        let (min_length, length_bits) = range_bits(&lengths);
+        let mut table = BitWriter::default();
+        match shared.first() {
+            Some(id) => {
+                table.write(self.new_ids[id] as u64, 32);
+                table.write(self.offsets[id], 32);
+            }
+            None => {
+                table.write(0, 32);
+                table.write(0, 32);
+            }
+        }
+        table.write(first_page.len() as u64, 32);
+        table.write(lengths.len() as u64, 32);
+        // One object in each group.
+        table.write(1, 16);
+        table.write(min_length, 32);
+        table.write(length_bits.into(), 16);
+        table.write_all(&lengths, min_length, length_bits);
+        // No signatures.
+        table.write_all(&vec![0; lengths.len()], 0, 1);
+        // The number of objects in each group, minus one.
+        table.write_all(&vec![0; lengths.len()], 0, 1);
+        table.into_bytes()
+    }
+}
+
+/// Get the least of the `values`, and the number of bits needed for
+/// the difference between each value and the least.
+fn range_bits(values: &[u64]) -> (u64, u8) {
+    let min = values.iter().min().cloned().unwrap_or(0);
+    let max = values.iter().max().cloned().unwrap_or(0);
+    (min, bits(max - min))
+}
+
+/// Get the number of bits needed to represent `value`.
+fn bits(value: u64) -> u8 {
+    (64 - value.leading_zeros()) as u8
+}
+
+/// A writer of the bit fields of the hint tables.
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    /// The number of bits used in the last byte, or 0 if it is full.
+    used: u8,
+}
+
+impl BitWriter {
+    /// Write the `bits` low bits of `value`, most significant first.
+    fn write(&mut self, value: u64, bits: u8) {
+        for bit in (0..bits).rev() {
+            if self.used == 0 {
+                self.data.push(0);
+            }
+            if (value >> bit) & 1 == 1 {
+                *self.data.last_mut().unwrap() |= 0x80 >> self.used;
+            }
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /// Write an item of the hint table for each page or group, as the
+    /// difference from `min`, and pad it to a whole byte.
+    fn write_all(&mut self, values: &[u64], min: u64, bits: u8) {
+        for value in values {
+            self.write(value - min, bits);
+        }
+        self.used = 0;
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Get the indirect references in an object, as the byte range of the
+/// object number and the object number.
+fn references(content: &[u8]) -> Vec<(Range<usize>, usize)> {
+    let is_white = |b: u8| b"\0\t\n\x0C\r ".contains(&b);
+    let is_delimiter = |b: u8| b"()<>[]{}/%".contains(&b);
+    // The end of a regular token, like a number or a keyword.
+    let token_end = |pos: usize| {
+        (pos..content.len())
+            .find(|&i| is_white(content[i]) || is_delimiter(content[i]))
+            .unwrap_or(content.len())
+    };
+    let skip_white = |pos: usize| {
+        (pos..content.len())
+            .find(|&i| !is_white(content[i]))
+            .unwrap_or(content.len())
+    };
+    let number = |range: Range<usize>| -> Option<usize> {
+        let token = &content[range];
+        if token.is_empty() || !token.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(token).ok()?.parse().ok()
+    };
+
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while pos < content.len() {
+        match content[pos] {
+            b'%' => {
+                while pos < content.len() && !b"\r\n".contains(&content[pos])
+                {
+                    pos += 1;
+                }
+            }
+            b'(' => {
+                let mut depth = 0;
+                while pos < content.len() {
+                    match content[pos] {
+                        b'\\' => pos += 1,
+                        b'(' => depth += 1,
+                        b')' => depth -= 1,
+                        _ => (),
+                    }
+                    pos += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            b'<' if content.get(pos + 1) == Some(&b'<') => pos += 2,
+            b'<' => {
+                while pos < content.len() && content[pos] != b'>' {
+                    pos += 1;
+                }
+            }
+            b'/' => pos = token_end(pos + 1),
+            b if is_white(b) || is_delimiter(b) => pos += 1,
+            _ => {
+                let end = token_end(pos);
+                if let Some(id) = number(pos..end) {
+                    // An object number, a generation number and R.
+                    let gen_start = skip_white(end);
+                    let gen_end = token_end(gen_start);
+                    let r = skip_white(gen_end);
+                    if gen_start > end
+                        && number(gen_start..gen_end).is_some()
+                        && r > gen_end
+                        && token_end(r) == r + 1
+                        && content[r] == b'R'
+                    {
+                        result.push((pos..end, id));
+                        pos = r + 1;
+                        continue;
+                    }
+                }
+                pos = end;
+            }
+        }
+    }
+    result
+}
+
+/// Renumber the indirect references in an object.
+fn renumber(content: &[u8], new_ids: &BTreeMap<usize, usize>) -> Vec<u8> {
+    let mut result = Vec::with_capacity(content.len());
+    let mut pos = 0;
+    for (range, id) in references(content) {
+        result.extend_from_slice(&content[pos..range.start]);
+        let id = new_ids.get(&id).cloned().unwrap_or(id);
+        result.extend_from_slice(id.to_string().as_bytes());
+        pos = range.end;
+    }
+    result.extend_from_slice(&content[pos..]);
+    result
+}
+
+#[test]
+fn test_references() {
+    let content = b"<< /Type /Page /Parent 2 0 R /Kids [ 10 0 R 11 0 R]\n   \
+                    /Title (not 3 0 R \\) 4 0 R) /F12 5 0 R /W [ 1 2 ] >>";
+    let ids = references(content)
+        .into_iter()
+        .map(|(_, id)| id)
+        .collect::<Vec<_>>();
+    assert_eq!(vec![2, 10, 11, 5], ids);
+    let new_ids = [(2, 7), (10, 100)].iter().cloned().collect();
+    assert_eq!(
+        &b"/Kids [ 100 0 R 11 0 R] /Parent 7 0 R"[..],
+        &renumber(b"/Kids [ 10 0 R 11 0 R] /Parent 2 0 R", &new_ids)[..],
+    );
+}
+
+#[test]
+fn test_bit_writer() {
+    let mut writer = BitWriter::default();
+    writer.write(1, 1);
+    writer.write(0b101, 3);
+    writer.write_all(&[3, 4, 5], 3, 2);
+    writer.write(0x1234, 16);
+    assert_eq!(
+        vec![0b1101_0001, 0b1000_0000, 0x12, 0x34],
+        writer.into_bytes()
+    );
+}
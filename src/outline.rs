@@ -1,4 +1,4 @@
-pub use crate::encoding::WIN_ANSI_ENCODING;
+use crate::textstring::text_string;
 use std::io::{self, Write};
 
 /// An item in the document outline.
@@ -35,9 +35,9 @@ impl OutlineItem {
         prev: Option<usize>,
         next: Option<usize>,
     ) -> io::Result<()> {
-        write!(output, "<< /Title (")?;
-        output.write_all(&WIN_ANSI_ENCODING.encode_string(&self.title))?;
-        writeln!(output, ")")?;
+        write!(output, "<< /Title ")?;
+        output.write_all(&text_string(&self.title))?;
+        writeln!(output)?;
         writeln!(output, "/Parent {} 0 R", parent_id)?;
         if let Some(id) = prev {
             writeln!(output, "/Prev {} 0 R", id)?;
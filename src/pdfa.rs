@@ -0,0 +1,56 @@
+//! PDF/A conformance levels, for documents made for archiving.
+use std::fmt;
+
+/// A PDF/A conformance level, see
+/// [Pdf::set_pdf_a](struct.Pdf.html#method.set_pdf_a).
+///
+/// Only the basic (`b`) levels are supported, which require that the
+/// document looks the same in any viewer, but not that it is tagged
+/// with a logical structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PdfA {
+    /// PDF/A-1b, ISO 19005-1, based on PDF 1.4.
+    /// Transparency, such as images with an alpha channel, and object
+    /// streams are not allowed.
+    A1b,
+    /// PDF/A-2b, ISO 19005-2, based on PDF 1.7.
+    /// Only PDF/A files can be attached.
+    A2b,
+    /// PDF/A-3b, ISO 19005-3, which is like PDF/A-2b, except that any
+    /// file can be attached, e.g. the XML of an electronic invoice.
+    A3b,
+}
+
+impl PdfA {
+    /// Get the part of ISO 19005, for the `pdfaid:part` XMP property.
+    pub(crate) fn part(self) -> u8 {
+        match self {
+            PdfA::A1b => 1,
+            PdfA::A2b => 2,
+            PdfA::A3b => 3,
+        }
+    }
+
+    /// Get the conformance level, for the `pdfaid:conformance` XMP
+    /// property.
+    pub(crate) fn conformance(self) -> &'static str {
+        "B"
+    }
+
+    /// Check if transparency, such as soft masks, is allowed.
+    pub(crate) fn allows_transparency(self) -> bool {
+        self != PdfA::A1b
+    }
+
+    /// Check if object streams and cross-reference streams are allowed.
+    pub(crate) fn allows_object_streams(self) -> bool {
+        self != PdfA::A1b
+    }
+}
+
+impl fmt::Display for PdfA {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = self.conformance().to_ascii_lowercase();
+        write!(f, "PDF/A-{}{}", self.part(), level)
+    }
+}
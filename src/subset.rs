@@ -0,0 +1,396 @@
+//! Subsetting of TrueType fonts, so that only the glyphs that are
+//! actually used in a document needs to be embedded.
+use crate::truetype::{invalid, read_i16, read_u16, read_u32, slice, Tables};
+use crate::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Create a subset of the TrueType font in `data`, containing the
+/// given `glyphs` and any glyphs they are composed of.
+///
+/// Glyph ids are kept as in the original font, the outlines of unused
+/// glyphs are just left empty.
+/// This way, the widths and glyph ids used in the pdf file are the
+/// same for the subset as for the complete font.
+/// If `cmap` is given, the subset gets a cmap table with those
+/// characters, as needed for a simple font.
+///
+/// Return the subset, and all glyphs in it, including the components.
+pub(crate) fn subset(
+    data: &[u8],
+    glyphs: &BTreeSet<u16>,
+    cmap: Option<&BTreeMap<char, u16>>,
+) -> Result<(Vec<u8>, BTreeSet<u16>)> {
+    let tables = Tables::parse(data)?;
+    let head = tables.get(b"head")?;
+    let maxp = tables.get(b"maxp")?;
+    let hhea = tables.get(b"hhea")?;
+    let hmtx = tables.get(b"hmtx")?;
+    let glyf = tables.get(b"glyf")?;
+    let loca = Loca {
+        data: tables.get(b"loca")?,
+        long: read_i16(head, 50)? != 0,
+    };
+    let num_glyphs = read_u16(maxp, 4)?;
+
+    let glyphs = with_components(glyphs, num_glyphs, glyf, &loca)?;
+    let new_num_glyphs = glyphs.iter().next_back().map_or(1, |&g| g + 1);
+
+    let mut new_glyf = Vec::new();
+    let mut new_loca =
+        Vec::with_capacity(4 * usize::from(new_num_glyphs) + 4);
+    for gid in 0..new_num_glyphs {
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        if glyphs.contains(&gid) {
+            new_glyf.extend_from_slice(loca.glyph(glyf, gid)?);
+            pad(&mut new_glyf);
+        }
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+
+    // The horizontal metrics are a number of advance width and left
+    // side bearing pairs, followed by left side bearings only.
+    let num_h_metrics = read_u16(hhea, 34)?.min(new_num_glyphs);
+    let hmtx_len = 4 * usize::from(num_h_metrics)
+        + 2 * usize::from(new_num_glyphs - num_h_metrics);
+    let new_hmtx = slice(hmtx, 0, hmtx_len)?.to_vec();
+
+    let mut new_head = head.to_vec();
+    new_head[8..12].copy_from_slice(&[0; 4]); // checkSumAdjustment
+    new_head[50..52].copy_from_slice(&1u16.to_be_bytes()); // long loca
+    let mut new_hhea = hhea.to_vec();
+    new_hhea[34..36].copy_from_slice(&num_h_metrics.to_be_bytes());
+    let mut new_maxp = maxp.to_vec();
+    new_maxp[4..6].copy_from_slice(&new_num_glyphs.to_be_bytes());
+
+    let mut result = BTreeMap::new();
+    result.insert(*b"head", new_head);
+    result.insert(*b"hhea", new_hhea);
+    result.insert(*b"maxp", new_maxp);
+    result.insert(*b"hmtx", new_hmtx);
+    result.insert(*b"loca", new_loca);
+    result.insert(*b"glyf", new_glyf);
+    if let Some(cmap) = cmap {
+        result.insert(*b"cmap", write_cmap(cmap));
+    }
+    for tag in [b"cvt ", b"fpgm", b"prep", b"OS/2"] {
+        if let Ok(table) = tables.get(tag) {
+            result.insert(*tag, table.to_vec());
+        }
+    }
+    Ok((write_font(result), glyphs))
+}
+
+/// Get a tag for a subset font, to be prepended to the font name.
+///
+/// The tag is six uppercase letters, computed from the glyphs in the
+/// subset, so that different subsets of a font get different names.
+pub(crate) fn subset_tag(glyphs: &BTreeSet<u16>) -> String {
+    let mut hash = fnv_hash(glyphs.iter().flat_map(|gid| gid.to_be_bytes()));
+    (0..6)
+        .map(|_| {
+            let letter = char::from(b'A' + (hash % 26) as u8);
+            hash /= 26;
+            letter
+        })
+        .collect()
+}
+
+/// Get the 64-bit FNV-1a hash of some bytes.
+///
+/// Unlike the hashers of the standard library, this gives the same
+/// hash on any platform and with any version of rust, so it can be
+/// used for names in the pdf file.
+pub(crate) fn fnv_hash<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The index to locations of a TrueType font.
+struct Loca<'a> {
+    data: &'a [u8],
+    long: bool,
+}
+
+impl<'a> Loca<'a> {
+    /// Get the data for a glyph from the glyf table.
+    fn glyph<'g>(&self, glyf: &'g [u8], gid: u16) -> Result<&'g [u8]> {
+        let gid = usize::from(gid);
+        let (start, end) = if self.long {
+            (
+                read_u32(self.data, 4 * gid)? as usize,
+                read_u32(self.data, 4 * gid + 4)? as usize,
+            )
+        } else {
+            (
+                2 * usize::from(read_u16(self.data, 2 * gid)?),
+                2 * usize::from(read_u16(self.data, 2 * gid + 2)?),
+            )
+        };
+        if end < start {
+            return Err(invalid("Bad glyph location in loca table"));
+        }
+        slice(glyf, start, end - start)
+    }
+}
+
+/// Get the glyph ids of `glyphs`, the `.notdef` glyph, and all glyphs
+/// that they are composed of.
+fn with_components(
+    glyphs: &BTreeSet<u16>,
+    num_glyphs: u16,
+    glyf: &[u8],
+    loca: &Loca,
+) -> Result<BTreeSet<u16>> {
+    let mut result = BTreeSet::new();
+    let mut todo = vec![0];
+    todo.extend(glyphs.iter().filter(|&&gid| gid < num_glyphs));
+    while let Some(gid) = todo.pop() {
+        if !result.insert(gid) {
+            continue;
+        }
+        let glyph = loca.glyph(glyf, gid)?;
+        if glyph.is_empty() || read_i16(glyph, 0)? >= 0 {
+            continue; // An empty or simple glyph.
+        }
+        let mut pos = 10;
+        loop {
+            let flags = read_u16(glyph, pos)?;
+            let component = read_u16(glyph, pos + 2)?;
+            if component < num_glyphs {
+                todo.push(component);
+            }
+            pos += if flags & 0x0001 != 0 { 8 } else { 6 };
+            if flags & 0x0008 != 0 {
+                pos += 2;
+            } else if flags & 0x0040 != 0 {
+                pos += 4;
+            } else if flags & 0x0080 != 0 {
+                pos += 8;
+            }
+            if flags & 0x0020 == 0 {
+                break;
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Write a cmap table with a single Windows unicode BMP subtable, with
+/// a segment for each character.
+fn write_cmap(cmap: &BTreeMap<char, u16>) -> Vec<u8> {
+    let mut segments = cmap
+        .iter()
+        .filter_map(|(&ch, &gid)| {
+            let code = u16::try_from(u32::from(ch)).ok()?;
+            Some((code, gid.wrapping_sub(code)))
+        })
+        .filter(|&(code, _)| code != 0xFFFF)
+        .collect::<Vec<_>>();
+    // The last segment must map 0xFFFF to glyph 0.
+    segments.push((0xFFFF, 1));
+
+    let seg_count = segments.len() as u16;
+    let mut search_range = 1u16;
+    let mut entry_selector = 0u16;
+    while search_range * 2 <= seg_count {
+        search_range *= 2;
+        entry_selector += 1;
+    }
+    let mut subtable = Vec::new();
+    let mut push =
+        |value: u16| subtable.extend_from_slice(&value.to_be_bytes());
+    push(4); // format
+    push(16 + 8 * seg_count); // length
+    push(0); // language
+    push(2 * seg_count);
+    push(2 * search_range);
+    push(entry_selector);
+    push(2 * seg_count - 2 * search_range);
+    for &(code, _) in &segments {
+        push(code); // endCode
+    }
+    push(0); // reservedPad
+    for &(code, _) in &segments {
+        push(code); // startCode
+    }
+    for &(_, delta) in &segments {
+        push(delta); // idDelta
+    }
+    for _ in &segments {
+        push(0); // idRangeOffset
+    }
+
+    let mut result = Vec::with_capacity(12 + subtable.len());
+    for value in &[0, 1, 3, 1] {
+        // version, number of subtables, platform and encoding id
+        result.extend_from_slice(&u16::to_be_bytes(*value));
+    }
+    result.extend_from_slice(&12u32.to_be_bytes());
+    result.extend_from_slice(&subtable);
+    result
+}
+
+/// Write a TrueType font file from its tables.
+fn write_font(tables: BTreeMap<[u8; 4], Vec<u8>>) -> Vec<u8> {
+    let num_tables = tables.len() as u16;
+    let mut search_range = 1;
+    let mut entry_selector = 0u16;
+    while search_range * 2 <= num_tables {
+        search_range *= 2;
+        entry_selector += 1;
+    }
+    let mut result = Vec::new();
+    result.extend_from_slice(&0x0001_0000_u32.to_be_bytes());
+    result.extend_from_slice(&num_tables.to_be_bytes());
+    result.extend_from_slice(&(search_range * 16).to_be_bytes());
+    result.extend_from_slice(&entry_selector.to_be_bytes());
+    result.extend_from_slice(
+        &(num_tables * 16 - search_range * 16).to_be_bytes(),
+    );
+
+    let mut offset = 12 + 16 * tables.len();
+    for (tag, table) in &tables {
+        result.extend_from_slice(tag);
+        result.extend_from_slice(&checksum(table).to_be_bytes());
+        result.extend_from_slice(&(offset as u32).to_be_bytes());
+        result.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) & !3;
+    }
+    let mut head_offset = None;
+    for (tag, table) in &tables {
+        if tag == b"head" {
+            head_offset = Some(result.len());
+        }
+        result.extend_from_slice(table);
+        pad(&mut result);
+    }
+    if let Some(head) = head_offset {
+        let adjustment = 0xB1B0_AFBA_u32.wrapping_sub(checksum(&result));
+        result[head + 8..head + 12]
+            .copy_from_slice(&adjustment.to_be_bytes());
+    }
+    result
+}
+
+/// The checksum of a table (or a complete font) is the sum of its
+/// contents as big-endian 32-bit words.
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// Pad data with zeros to a multiple of four bytes.
+// `usize::is_multiple_of` needs Rust 1.87, newer than this crate needs.
+#[allow(clippy::manual_is_multiple_of)]
+fn pad(data: &mut Vec<u8>) {
+    while data.len() % 4 != 0 {
+        data.push(0);
+    }
+}
+
+/// Get the checksum and contents of each table in a font file.
+#[cfg(test)]
+fn test_font_tables(data: &[u8]) -> BTreeMap<[u8; 4], (u32, &[u8])> {
+    let num_tables = usize::from(read_u16(data, 4).unwrap());
+    (0..num_tables)
+        .map(|i| {
+            let record = slice(data, 12 + 16 * i, 16).unwrap();
+            let offset = read_u32(record, 8).unwrap() as usize;
+            let length = read_u32(record, 12).unwrap() as usize;
+            let tag = [record[0], record[1], record[2], record[3]];
+            let table = slice(data, offset, length).unwrap();
+            (tag, (read_u32(record, 4).unwrap(), table))
+        })
+        .collect()
+}
+
+#[test]
+fn test_subset_keeps_components() {
+    // Glyph 5 is Ä, composed of A (3) and the dot accent (4) twice.
+    let data = include_bytes!("../data/PdfCanvasTest.ttf");
+    let glyphs = [5].iter().cloned().collect();
+    let (subset, all) = subset(data, &glyphs, None).unwrap();
+    assert_eq!(vec![0, 3, 4, 5], all.into_iter().collect::<Vec<_>>());
+
+    let original = Tables::parse(data).unwrap();
+    let original_loca = Loca {
+        data: original.get(b"loca").unwrap(),
+        long: false,
+    };
+    let original_glyf = original.get(b"glyf").unwrap();
+    let tables = Tables::parse(&subset).unwrap();
+    let loca = Loca {
+        data: tables.get(b"loca").unwrap(),
+        long: true,
+    };
+    let glyf = tables.get(b"glyf").unwrap();
+    // The glyphs after the last one used are left out.
+    assert_eq!(6, read_u16(tables.get(b"maxp").unwrap(), 4).unwrap());
+    assert_eq!(4 * 6 + 4, tables.get(b"loca").unwrap().len());
+    assert_eq!(4 * 6, tables.get(b"hmtx").unwrap().len());
+    for gid in 0..6 {
+        let glyph = loca.glyph(glyf, gid).unwrap();
+        if [0, 3, 4, 5].contains(&gid) {
+            let expected = original_loca.glyph(original_glyf, gid).unwrap();
+            assert_eq!(expected, glyph, "glyph {}", gid);
+        } else {
+            assert!(glyph.is_empty(), "glyph {}", gid);
+        }
+    }
+    assert!(tables.get(b"cmap").is_err());
+}
+
+#[test]
+fn test_subset_checksums() {
+    let data = include_bytes!("../data/PdfCanvasTest.ttf");
+    let glyphs = [3, 4].iter().cloned().collect();
+    let cmap = [('A', 3), ('\u{2D9}', 4)].iter().cloned().collect();
+    let (subset, _) = subset(data, &glyphs, Some(&cmap)).unwrap();
+    for (tag, (sum, table)) in test_font_tables(&subset) {
+        if &tag == b"head" {
+            let mut table = table.to_vec();
+            table[8..12].copy_from_slice(&[0; 4]);
+            assert_eq!(sum, checksum(&table));
+        } else {
+            assert_eq!(sum, checksum(table), "{:?}", tag);
+        }
+    }
+    assert_eq!(0xB1B0_AFBA, checksum(&subset));
+
+    let tables = Tables::parse(&subset).unwrap();
+    let glyf = tables.get(b"glyf").unwrap();
+    let loca = tables.get(b"loca").unwrap();
+    let offsets = loca
+        .chunks(4)
+        .map(|chunk| read_u32(chunk, 0).unwrap() as usize)
+        .collect::<Vec<_>>();
+    assert_eq!(0, offsets[0]);
+    assert!(offsets.windows(2).all(|w| w[0] <= w[1]));
+    assert!(offsets.iter().all(|offset| offset % 4 == 0));
+    assert_eq!(glyf.len(), offsets[offsets.len() - 1]);
+    assert_eq!(
+        cmap,
+        crate::truetype::parse_cmap(tables.get(b"cmap").unwrap(), 7).unwrap()
+    );
+}
+
+#[test]
+fn test_subset_tag() {
+    let glyphs = [0, 3, 5].iter().cloned().collect::<BTreeSet<_>>();
+    assert_eq!("DYYUDK", subset_tag(&glyphs));
+    assert_eq!(subset_tag(&glyphs), subset_tag(&glyphs.clone()));
+    let other = [0, 3, 4].iter().cloned().collect();
+    assert_ne!(subset_tag(&glyphs), subset_tag(&other));
+    assert!(subset_tag(&other).chars().all(|c| c.is_ascii_uppercase()));
+}
+
+#[test]
+fn test_fnv_hash() {
+    assert_eq!(0xcbf2_9ce4_8422_2325, fnv_hash(*b""));
+    assert_eq!(0xaf63_dc4c_8601_ec8c, fnv_hash(*b"a"));
+    assert_eq!(0x8594_4171_f739_67e8, fnv_hash(b"foobar".iter().copied()));
+}
@@ -0,0 +1,30 @@
+//! Templates, written as form XObjects.
+use std::fmt;
+
+/// A reference to a template in a document.
+///
+/// A template is content that is rendered once, by
+/// [Pdf::render_template](struct.Pdf.html#method.render_template), and
+/// can then be drawn any number of times, on any page, with
+/// [Canvas::draw_template](struct.Canvas.html#method.draw_template).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TemplateRef {
+    id: usize,
+}
+
+impl TemplateRef {
+    pub(crate) fn new(id: usize) -> Self {
+        TemplateRef { id }
+    }
+
+    /// Get the object id of the form XObject.
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl fmt::Display for TemplateRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/Fm{}", self.id)
+    }
+}
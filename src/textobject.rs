@@ -1,7 +1,7 @@
-use crate::encoding::{Encoding, WIN_ANSI_ENCODING};
-use crate::fontref::FontRef;
+use crate::error::{check_finite, PdfError, Result};
+use crate::fontref::{FontRef, FontRegistry};
 use crate::graphicsstate::Color;
-use std::io::{self, Write};
+use std::io::Write;
 
 /// A text object is where text is put on the canvas.
 ///
@@ -35,48 +35,88 @@ use std::io::{self, Write};
 /// ```
 pub struct TextObject<'a> {
     output: &'a mut dyn Write,
-    encoding: Encoding,
+    fonts: &'a mut FontRegistry,
+    font: Option<FontRef>,
+    size: f32,
+    /// The font last selected in the pdf, which is a part of the
+    /// current font if it has an automatic encoding.
+    shown_font: Option<FontRef>,
+    /// The font, size and shown font of each graphics state saved and
+    /// not yet restored, as the text state is restored with it.
+    saved_states: Vec<(Option<FontRef>, f32, Option<FontRef>)>,
 }
 
 impl<'a> TextObject<'a> {
     // Should not be called by user code.
-    pub(crate) fn new(output: &'a mut dyn Write) -> Self {
+    pub(crate) fn new(
+        output: &'a mut dyn Write,
+        fonts: &'a mut FontRegistry,
+    ) -> Self {
         TextObject {
             output,
-            encoding: WIN_ANSI_ENCODING.clone(),
+            fonts,
+            font: None,
+            size: 0.0,
+            shown_font: None,
+            saved_states: Vec::new(),
+        }
+    }
+
+    /// Check that every saved graphics state has been restored, when
+    /// the text object is done.
+    pub(crate) fn check_restored(&self) -> Result<()> {
+        match self.saved_states.len() {
+            0 => Ok(()),
+            n => Err(PdfError::Structure(format!(
+                "{} gsave without a matching grestore in text object",
+                n
+            ))),
         }
     }
 
     /// Set the font and font-size to be used by the following text
     /// operations.
-    pub fn set_font(&mut self, font: &FontRef, size: f32) -> io::Result<()> {
-        self.encoding = font.get_encoding().clone();
-        writeln!(self.output, "{} {} Tf", font, size)
+    pub fn set_font(&mut self, font: &FontRef, size: f32) -> Result<()> {
+        check_finite(&[size])?;
+        self.fonts.use_font(font)?;
+        self.font = Some(font.clone());
+        self.size = size;
+        self.shown_font = Some(font.clone());
+        writeln!(self.output, "{} {} Tf", font, size)?;
+        Ok(())
     }
     /// Set leading, the vertical distance from a line of text to the next.
     /// This is important for the [show_line](#method.show_line) method.
-    pub fn set_leading(&mut self, leading: f32) -> io::Result<()> {
-        writeln!(self.output, "{} TL", leading)
+    pub fn set_leading(&mut self, leading: f32) -> Result<()> {
+        check_finite(&[leading])?;
+        writeln!(self.output, "{} TL", leading)?;
+        Ok(())
     }
     /// Set the rise above the baseline for coming text.  Calling
     /// set_rise again with a zero argument will get back to the old
     /// baseline.
-    pub fn set_rise(&mut self, rise: f32) -> io::Result<()> {
-        writeln!(self.output, "{} Ts", rise)
+    pub fn set_rise(&mut self, rise: f32) -> Result<()> {
+        check_finite(&[rise])?;
+        writeln!(self.output, "{} Ts", rise)?;
+        Ok(())
     }
     /// Set the amount of extra space between characters, in 1/1000
     /// text unit.
-    pub fn set_char_spacing(&mut self, a_c: f32) -> io::Result<()> {
-        writeln!(self.output, "{} Tc", a_c)
+    pub fn set_char_spacing(&mut self, a_c: f32) -> Result<()> {
+        check_finite(&[a_c])?;
+        writeln!(self.output, "{} Tc", a_c)?;
+        Ok(())
     }
     /// Set the amount of extra space between words, in 1/1000
     /// text unit.
-    pub fn set_word_spacing(&mut self, a_w: f32) -> io::Result<()> {
-        writeln!(self.output, "{} Tw", a_w)
+    pub fn set_word_spacing(&mut self, a_w: f32) -> Result<()> {
+        check_finite(&[a_w])?;
+        writeln!(self.output, "{} Tw", a_w)?;
+        Ok(())
     }
 
     /// Set color for stroking operations.
-    pub fn set_stroke_color(&mut self, color: Color) -> io::Result<()> {
+    pub fn set_stroke_color(&mut self, color: Color) -> Result<()> {
         let norm = |c| f32::from(c) / 255.0;
         match color {
             Color::RGB { red, green, blue } => writeln!(
@@ -85,12 +125,15 @@ impl<'a> TextObject<'a> {
                 norm(red),
                 norm(green),
                 norm(blue),
-            ),
-            Color::Gray { gray } => writeln!(self.output, "{} G", norm(gray)),
+            )?,
+            Color::Gray { gray } => {
+                writeln!(self.output, "{} G", norm(gray))?
+            }
         }
+        Ok(())
     }
     /// Set color for non-stroking operations.
-    pub fn set_fill_color(&mut self, color: Color) -> io::Result<()> {
+    pub fn set_fill_color(&mut self, color: Color) -> Result<()> {
         let norm = |c| f32::from(c) / 255.0;
         match color {
             Color::RGB { red, green, blue } => writeln!(
@@ -99,9 +142,12 @@ impl<'a> TextObject<'a> {
                 norm(red),
                 norm(green),
                 norm(blue),
-            ),
-            Color::Gray { gray } => writeln!(self.output, "{} g", norm(gray)),
+            )?,
+            Color::Gray { gray } => {
+                writeln!(self.output, "{} g", norm(gray))?
+            }
         }
+        Ok(())
     }
 
     /// Move text position.
@@ -110,14 +156,23 @@ impl<'a> TextObject<'a> {
     /// TextObject, (x, y) refers to the same point as for
     /// [Canvas::move_to](struct.Canvas.html#method.move_to), after that,
     /// the point is relative to the earlier pos.
-    pub fn pos(&mut self, x: f32, y: f32) -> io::Result<()> {
-        writeln!(self.output, "{} {} Td", x, y)
+    pub fn pos(&mut self, x: f32, y: f32) -> Result<()> {
+        check_finite(&[x, y])?;
+        writeln!(self.output, "{} {} Td", x, y)?;
+        Ok(())
     }
     /// Show a text.
-    pub fn show(&mut self, text: &str) -> io::Result<()> {
-        write!(self.output, "(")?;
-        self.output.write_all(&self.encoding.encode_string(text))?;
-        writeln!(self.output, ") Tj")
+    ///
+    /// A font must be set with [set_font](#method.set_font) first, and
+    /// every character of the text must be in the font, or an error is
+    /// returned.
+    pub fn show(&mut self, text: &str) -> Result<()> {
+        for (font, string) in self.encode(text)? {
+            self.switch_font(font)?;
+            self.output.write_all(&string)?;
+            writeln!(self.output, " Tj")?;
+        }
+        Ok(())
     }
 
     /// Show one or more text strings, allowing individual glyph positioning.
@@ -142,30 +197,145 @@ impl<'a> TextObject<'a> {
     /// # }).unwrap();
     /// # document.finish().unwrap();
     /// ```
-    pub fn show_adjusted(&mut self, param: &[(&str, i32)]) -> io::Result<()> {
-        write!(self.output, "[")?;
-        for &(text, offset) in param {
-            write!(self.output, "(")?;
-            self.output.write_all(&self.encoding.encode_string(text))?;
-            write!(self.output, ") {} ", offset)?
+    pub fn show_adjusted(&mut self, param: &[(&str, i32)]) -> Result<()> {
+        let items = param
+            .iter()
+            .map(|&(text, offset)| (text, Some(offset)))
+            .collect::<Vec<_>>();
+        self.show_array(&items)
+    }
+    /// Show a text, with the kerning of the current font.
+    ///
+    /// The text is shown like [show_adjusted](#method.show_adjusted),
+    /// with the adjustments given by the kerning pairs of the font.
+    /// The width of the text is given by
+    /// [FontRef::get_kerned_width](struct.FontRef.html#method.get_kerned_width).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use pdf_canvas::{Pdf, BuiltinFont, FontSource};
+    /// # let mut document = Pdf::create("foo.pdf").unwrap();
+    /// # document.render_page(180.0, 240.0, |canvas| {
+    /// # let serif = canvas.get_font(BuiltinFont::Times_Roman);
+    /// # canvas.text(|t| {
+    /// #    t.set_font(&serif, 14.0)?;
+    /// t.show_kerned("WAVE")
+    /// # })
+    /// # }).unwrap();
+    /// # document.finish().unwrap();
+    /// ```
+    pub fn show_kerned(&mut self, text: &str) -> Result<()> {
+        let font = match self.font {
+            Some(ref font) => font.clone(),
+            None => return self.show(text),
+        };
+        let mut items = Vec::new();
+        let mut start = 0;
+        for ((i, left), right) in
+            text.char_indices().zip(text.chars().skip(1))
+        {
+            let kerning = font.get_kerning(left, right);
+            if kerning != 0 {
+                let end = i + left.len_utf8();
+                // A positive TJ adjustment brings letters closer.
+                items.push((&text[start..end], Some(-kerning)));
+                start = end;
+            }
         }
-        writeln!(self.output, "] TJ")
+        items.push((&text[start..], None));
+        self.show_array(&items)
     }
     /// Show a text as a line.  See also [set_leading](#method.set_leading).
-    pub fn show_line(&mut self, text: &str) -> io::Result<()> {
-        write!(self.output, "(")?;
-        self.output.write_all(&self.encoding.encode_string(text))?;
-        writeln!(self.output, ") '")
+    pub fn show_line(&mut self, text: &str) -> Result<()> {
+        let mut operator = "'";
+        for (font, string) in self.encode(text)? {
+            self.switch_font(font)?;
+            self.output.write_all(&string)?;
+            writeln!(self.output, " {}", operator)?;
+            operator = "Tj";
+        }
+        Ok(())
+    }
+    /// Show texts with optional adjustments after them, as a TJ array.
+    fn show_array(&mut self, items: &[(&str, Option<i32>)]) -> Result<()> {
+        let items = items
+            .iter()
+            .map(|&(text, offset)| Ok((self.encode(text)?, offset)))
+            .collect::<Result<Vec<_>>>()?;
+        let first_font = items.iter().flat_map(|(strings, _)| strings).next();
+        if let Some((font, _)) = first_font {
+            self.switch_font(font.clone())?;
+        }
+        write!(self.output, "[")?;
+        for (strings, offset) in items {
+            for (font, string) in strings {
+                // A font can not be set within a TJ array.
+                if Some(&font) != self.shown_font.as_ref() {
+                    writeln!(self.output, "] TJ")?;
+                    self.switch_font(font)?;
+                    write!(self.output, "[")?;
+                }
+                self.output.write_all(&string)?;
+            }
+            if let Some(offset) = offset {
+                write!(self.output, " {} ", offset)?;
+            }
+        }
+        writeln!(self.output, "] TJ")?;
+        Ok(())
+    }
+    /// Get the pdf strings for `text` in the current font, with the
+    /// font to show each string in.
+    ///
+    /// This may be several parts of a builtin font with an automatic
+    /// encoding, see
+    /// [Pdf::set_auto_encoding](struct.Pdf.html#method.set_auto_encoding).
+    fn encode(&mut self, text: &str) -> Result<Vec<(FontRef, Vec<u8>)>> {
+        match self.font {
+            Some(ref font) => self.fonts.encode_text(font, text),
+            None => Err(PdfError::Structure(
+                "Text shown before a font is set".to_string(),
+            )),
+        }
+    }
+    /// Select a font in the pdf, unless it is already selected.
+    fn switch_font(&mut self, font: FontRef) -> Result<()> {
+        if self.shown_font.as_ref() != Some(&font) {
+            writeln!(self.output, "{} {} Tf", font, self.size)?;
+            self.shown_font = Some(font);
+        }
+        Ok(())
     }
     /// Push the graphics state on a stack.
-    pub fn gsave(&mut self) -> io::Result<()> {
-        // TODO Push current encoding in self?
-        writeln!(self.output, "q")
+    pub fn gsave(&mut self) -> Result<()> {
+        writeln!(self.output, "q")?;
+        self.saved_states.push((
+            self.font.clone(),
+            self.size,
+            self.shown_font.clone(),
+        ));
+        Ok(())
     }
     /// Pop a graphics state from the [gsave](#method.gsave) stack and
     /// restore it.
-    pub fn grestore(&mut self) -> io::Result<()> {
-        // TODO Pop current encoding in self?
-        writeln!(self.output, "Q")
+    ///
+    /// Return a
+    /// [PdfError::Structure](enum.PdfError.html#variant.Structure) if
+    /// the stack is empty.
+    pub fn grestore(&mut self) -> Result<()> {
+        let (font, size, shown_font) = match self.saved_states.pop() {
+            Some(state) => state,
+            None => {
+                return Err(PdfError::Structure(
+                    "grestore without a matching gsave".to_string(),
+                ))
+            }
+        };
+        writeln!(self.output, "Q")?;
+        self.font = font;
+        self.size = size;
+        self.shown_font = shown_font;
+        Ok(())
     }
 }
@@ -0,0 +1,123 @@
+//! Text strings, for document metadata, outline titles and file names.
+//!
+//! A text string is written in PDFDocEncoding when possible, and
+//! otherwise in UTF-16BE with a byte order mark.
+
+/// Get the pdf string for `text`, including the string delimiters.
+pub(crate) fn text_string(text: &str) -> Vec<u8> {
+    let bytes = text_string_bytes(text);
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        let mut result = b"<".to_vec();
+        for byte in bytes {
+            result.extend(format!("{:02X}", byte).bytes());
+        }
+        result.push(b'>');
+        return result;
+    }
+    let mut result = Vec::with_capacity(bytes.len() + 2);
+    result.push(b'(');
+    for code in bytes {
+        match code {
+            b'\\' | b'(' | b')' => result.extend(&[b'\\', code]),
+            b'\n' => result.extend(b"\\n"),
+            b'\r' => result.extend(b"\\r"),
+            code => result.push(code),
+        }
+    }
+    result.push(b')');
+    result
+}
+
+/// Get the bytes of `text` as a text string, without any escaping.
+///
+/// This is the PDFDocEncoding of the text, or if that is not possible,
+/// UTF-16BE with a byte order mark.
+pub(crate) fn text_string_bytes(text: &str) -> Vec<u8> {
+    let codes = text.chars().map(pdf_doc_code).collect::<Option<Vec<_>>>();
+    match codes {
+        // PDFDocEncoding text can not start like a byte order mark.
+        Some(codes) if !codes.starts_with(&[0xFE, 0xFF]) => codes,
+        _ => {
+            let mut result = vec![0xFE, 0xFF];
+            for unit in text.encode_utf16() {
+                result.extend_from_slice(&unit.to_be_bytes());
+            }
+            result
+        }
+    }
+}
+
+/// Get the PDFDocEncoding code for a character, if it has one.
+fn pdf_doc_code(ch: char) -> Option<u8> {
+    match ch {
+        '\t' | '\n' | '\r' | ' '..='~' => Some(ch as u8),
+        // Latin-1, except the soft hyphen.
+        '\u{A1}'..='\u{FF}' if ch != '\u{AD}' => Some(ch as u8),
+        '€' => Some(0xA0),
+        _ => PDF_DOC_SPECIALS
+            .iter()
+            .find(|&&(c, _)| c == ch)
+            .map(|&(_, code)| code),
+    }
+}
+
+/// The characters of PDFDocEncoding that are not in ASCII or Latin-1.
+const PDF_DOC_SPECIALS: [(char, u8); 39] = [
+    ('˘', 0x18),
+    ('ˇ', 0x19),
+    ('ˆ', 0x1A),
+    ('˙', 0x1B),
+    ('˝', 0x1C),
+    ('˛', 0x1D),
+    ('˚', 0x1E),
+    ('˜', 0x1F),
+    ('•', 0x80),
+    ('†', 0x81),
+    ('‡', 0x82),
+    ('…', 0x83),
+    ('—', 0x84),
+    ('–', 0x85),
+    ('ƒ', 0x86),
+    ('⁄', 0x87),
+    ('‹', 0x88),
+    ('›', 0x89),
+    ('−', 0x8A),
+    ('‰', 0x8B),
+    ('„', 0x8C),
+    ('“', 0x8D),
+    ('”', 0x8E),
+    ('‘', 0x8F),
+    ('’', 0x90),
+    ('‚', 0x91),
+    ('™', 0x92),
+    ('ﬁ', 0x93),
+    ('ﬂ', 0x94),
+    ('Ł', 0x95),
+    ('Œ', 0x96),
+    ('Š', 0x97),
+    ('Ÿ', 0x98),
+    ('Ž', 0x99),
+    ('ı', 0x9A),
+    ('ł', 0x9B),
+    ('œ', 0x9C),
+    ('š', 0x9D),
+    ('ž', 0x9E),
+];
+
+#[test]
+fn test_text_string() {
+    assert_eq!(b"(Hello)".to_vec(), text_string("Hello"));
+    assert_eq!(
+        b"(a \\(b\\) \\\\ c\\n)".to_vec(),
+        text_string("a (b) \\ c\n")
+    );
+    assert_eq!(b"(R\xE4ksm\xF6rg\xE5s)".to_vec(), text_string("Räksmörgås"));
+    assert_eq!(
+        b"(\x95\xF3d\x9E \x80 \xA0)".to_vec(),
+        text_string("Łódž • €")
+    );
+    assert_eq!(b"<FEFF014100F30064017A>".to_vec(), text_string("Łódź"));
+    assert_eq!(b"<FEFF03B1D83DDE00>".to_vec(), text_string("α😀"));
+    assert_eq!(b"<FEFF00AD>".to_vec(), text_string("\u{AD}"));
+    assert_eq!(b"<FEFF00FE00FF>".to_vec(), text_string("þÿ"));
+}
@@ -0,0 +1,95 @@
+//! ToUnicode CMaps, that map the codes shown in a font back to text,
+//! so that text can be searched and copied from a pdf file.
+use crate::encoding::Encoding;
+use crate::{Pdf, PdfOutput, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+/// Get the codes used to show `chars` in a simple font with the given
+/// `encoding`, with the char for each code.
+///
+/// Characters that are not in the encoding need no mapping, since
+/// `FontRegistry::encode_text` rejects them with `PdfError::Encoding`.
+/// The builtin Symbol and ZapfDingbats encodings also map each code to
+/// the char with the same number, so when two chars are shown with the
+/// same code, the char that is not the code itself is preferred.
+pub(crate) fn simple_font_codes(
+    encoding: &Encoding,
+    chars: &BTreeSet<char>,
+) -> BTreeMap<u16, char> {
+    let mut result = BTreeMap::new();
+    for &ch in chars {
+        if let Some(code) = encoding.encode_char(ch) {
+            let code = u16::from(code);
+            let old = result.entry(code).or_insert(ch);
+            if u32::from(*old) == u32::from(code) {
+                *old = ch;
+            }
+        }
+    }
+    result
+}
+
+/// Write a ToUnicode CMap stream mapping each of the `codes` to its
+/// char, and return its object id.
+///
+/// Codes are written as one byte for a simple font and as two bytes
+/// for a composite font.
+pub(crate) fn write_to_unicode(
+    pdf: &mut Pdf<dyn PdfOutput + '_>,
+    codes: &BTreeMap<u16, char>,
+    composite: bool,
+) -> Result<usize> {
+    let mut cmap = Vec::new();
+    let (range, width) = if composite {
+        ("<0000> <FFFF>", 4)
+    } else {
+        ("<00> <FF>", 2)
+    };
+    write!(
+        cmap,
+        "/CIDInit /ProcSet findresource begin\n\
+         12 dict begin\n\
+         begincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) \
+         /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n\
+         /CMapType 2 def\n\
+         1 begincodespacerange\n\
+         {}\n\
+         endcodespacerange\n",
+        range,
+    )?;
+    let codes = codes.iter().collect::<Vec<_>>();
+    // There may be at most 100 mappings in each bfchar block.
+    for block in codes.chunks(100) {
+        writeln!(cmap, "{} beginbfchar", block.len())?;
+        for &(code, ch) in block {
+            write!(cmap, "<{:0width$X}> <", code, width = width)?;
+            for unit in ch.encode_utf16(&mut [0; 2]) {
+                write!(cmap, "{:04X}", unit)?;
+            }
+            writeln!(cmap, ">")?;
+        }
+        writeln!(cmap, "endbfchar")?;
+    }
+    write!(
+        cmap,
+        "endcmap\n\
+         CMapName currentdict /CMap defineresource pop\n\
+         end\n\
+         end\n"
+    )?;
+    pdf.write_stream("", &cmap)
+}
+
+#[test]
+fn test_simple_font_codes() {
+    use crate::encoding::SYMBOL_ENCODING;
+    let chars = "aα∈Ł".chars().collect();
+    let codes = simple_font_codes(&SYMBOL_ENCODING, &chars);
+    assert_eq!(
+        vec![(0o141, 'α'), (0o316, '∈')],
+        codes.into_iter().collect::<Vec<_>>()
+    );
+}
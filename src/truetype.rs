@@ -0,0 +1,812 @@
+use crate::encoding::{Encoding, IDENTITY_H_ENCODING, WIN_ANSI_ENCODING};
+use crate::fontmetrics::FontMetrics;
+use crate::fontsource::FontSource;
+use crate::subset::{fnv_hash, subset, subset_tag};
+use crate::tounicode::{simple_font_codes, write_to_unicode};
+use crate::{Pdf, PdfError, PdfOutput, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A TrueType font, to be embedded in the pdf file.
+///
+/// By default, the font is written as a simple TrueType font using
+/// WinAnsiEncoding.
+/// The font program is embedded as a FontFile2 stream, subsetted to
+/// the glyphs that are used in the document.
+/// To use characters outside of WinAnsiEncoding, use the font as a
+/// [composite](#method.composite) font.
+/// Only fonts with TrueType outlines (a `glyf` table) are supported,
+/// not OpenType fonts with CFF outlines.
+///
+/// Cloning a TrueTypeFont is cheap, the font data is shared.
+///
+/// # Example
+///
+/// ```no_run
+/// # use pdf_canvas::{Pdf, TrueTypeFont};
+/// let font = TrueTypeFont::from_file("fonts/DejaVuSans.ttf")
+///     .expect("Read font file");
+/// let mut document = Pdf::create("truetype.pdf").unwrap();
+/// document.render_page(180.0, 240.0, |canvas| {
+///     let font = canvas.get_font(font.clone());
+///     canvas.text(|t| {
+///         t.set_font(&font, 14.0)?;
+///         t.pos(10.0, 200.0)?;
+///         t.show("Hello World!")
+///     })
+/// }).unwrap();
+/// document.finish().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct TrueTypeFont {
+    data: Arc<Vec<u8>>,
+    hash: u64,
+    name: String,
+    units_per_em: u16,
+    bbox: [i16; 4],
+    ascent: i16,
+    descent: i16,
+    cap_height: i16,
+    italic_angle: f32,
+    weight: u16,
+    fixed_pitch: bool,
+    cmap: BTreeMap<char, u16>,
+    advances: Vec<u16>,
+    metrics: FontMetrics,
+    composite: bool,
+}
+
+impl TrueTypeFont {
+    /// Read a TrueType font from a `.ttf` file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TrueTypeFont> {
+        TrueTypeFont::parse(fs::read(path)?)
+    }
+
+    /// Create a TrueType font from the contents of a `.ttf` file.
+    pub fn from_bytes(data: &[u8]) -> Result<TrueTypeFont> {
+        TrueTypeFont::parse(data.to_vec())
+    }
+
+    /// Use this font as a composite (Type0) font with the Identity-H
+    /// encoding.
+    ///
+    /// A simple font can only show the characters of WinAnsiEncoding,
+    /// but a composite font can show any character that the font has a
+    /// glyph for, at the cost of using two bytes per character in the
+    /// page contents.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pdf_canvas::{Pdf, TrueTypeFont};
+    /// let font = TrueTypeFont::from_file("fonts/DejaVuSans.ttf")
+    ///     .expect("Read font file")
+    ///     .composite();
+    /// let mut document = Pdf::create("unicode.pdf").unwrap();
+    /// document.render_page(180.0, 240.0, |canvas| {
+    ///     let font = canvas.get_font(font.clone());
+    ///     canvas.text(|t| {
+    ///         t.set_font(&font, 14.0)?;
+    ///         t.pos(10.0, 200.0)?;
+    ///         t.show("Zażółć gęślą jaźń")?;
+    ///         t.show_line("Γειά σου Κόσμε")
+    ///     })
+    /// }).unwrap();
+    /// document.finish().unwrap();
+    /// ```
+    pub fn composite(mut self) -> TrueTypeFont {
+        self.composite = true;
+        self
+    }
+
+    fn parse(data: Vec<u8>) -> Result<TrueTypeFont> {
+        let tables = Tables::parse(&data)?;
+
+        let head = tables.get(b"head")?;
+        if read_u32(head, 12)? != 0x5F0F_3CF5 {
+            return Err(invalid("Bad magic number in head table"));
+        }
+        let units_per_em = read_u16(head, 18)?;
+        if units_per_em == 0 {
+            return Err(invalid("Zero unitsPerEm in head table"));
+        }
+        let bbox = [
+            read_i16(head, 36)?,
+            read_i16(head, 38)?,
+            read_i16(head, 40)?,
+            read_i16(head, 42)?,
+        ];
+
+        let hhea = tables.get(b"hhea")?;
+        let num_h_metrics = usize::from(read_u16(hhea, 34)?);
+        let num_glyphs = usize::from(read_u16(tables.get(b"maxp")?, 4)?);
+        let hmtx = tables.get(b"hmtx")?;
+        let mut advances = Vec::with_capacity(num_glyphs);
+        for gid in 0..num_h_metrics.min(num_glyphs) {
+            advances.push(read_u16(hmtx, 4 * gid)?);
+        }
+        let last = advances.last().cloned().unwrap_or(0);
+        advances.resize(num_glyphs, last);
+
+        let (mut ascent, mut descent) =
+            (read_i16(hhea, 4)?, read_i16(hhea, 6)?);
+        let mut cap_height = ascent;
+        let mut x_height = None;
+        let mut weight = 400;
+        if let Ok(os2) = tables.get(b"OS/2") {
+            weight = read_u16(os2, 4)?;
+            ascent = read_i16(os2, 68)?;
+            descent = read_i16(os2, 70)?;
+            if read_u16(os2, 0)? >= 2 {
+                x_height = Some(read_i16(os2, 86)?);
+                cap_height = read_i16(os2, 88)?;
+            }
+        }
+        let (italic_angle, fixed_pitch) = match tables.get(b"post") {
+            Ok(post) => (
+                read_u32(post, 4)? as i32 as f32 / 65536.0,
+                read_u32(post, 12)? != 0,
+            ),
+            Err(_) => (0.0, false),
+        };
+
+        let name = parse_name(tables.get(b"name")?)
+            .ok_or_else(|| invalid("No PostScript name in font"))?;
+        let cmap = parse_cmap(tables.get(b"cmap")?, num_glyphs)?;
+
+        let mut font = TrueTypeFont {
+            hash: fnv_hash(data.iter().copied()),
+            data: Arc::new(data),
+            name,
+            units_per_em,
+            bbox,
+            ascent,
+            descent,
+            cap_height,
+            italic_angle,
+            weight,
+            fixed_pitch,
+            cmap,
+            advances,
+            metrics: FontMetrics::from_slice(&[]),
+            composite: false,
+        };
+        let widths = (32..=255)
+            .map(|code| (code, font.get_code_width(code)))
+            .collect::<Vec<_>>();
+        let scaled = |value| font.scale(value) as f32;
+        font.metrics = FontMetrics::from_slice(&widths).with_font_info(
+            [
+                scaled(bbox[0]),
+                scaled(bbox[1]),
+                scaled(bbox[2]),
+                scaled(bbox[3]),
+            ],
+            scaled(ascent),
+            scaled(descent),
+            scaled(cap_height),
+            x_height.map(scaled),
+            italic_angle,
+        );
+        Ok(font)
+    }
+
+    /// Get the width of a glyph in thousands of unit of text space.
+    fn get_glyph_width(&self, gid: u16) -> u16 {
+        let advance = self.advances.get(usize::from(gid)).cloned();
+        (u32::from(advance.unwrap_or(0)) * 1000
+            / u32::from(self.units_per_em)) as u16
+    }
+
+    /// Get the width of a character code in the WinAnsiEncoding.
+    fn get_code_width(&self, code: u8) -> u16 {
+        let gid = WIN_ANSI_ENCODING
+            .decode_char(code)
+            .map(|ch| self.get_glyph_id(ch))
+            .unwrap_or(0);
+        self.get_glyph_width(gid)
+    }
+
+    /// Scale a value in font units to thousands of unit of text space.
+    fn scale(&self, value: i16) -> i32 {
+        i32::from(value) * 1000 / i32::from(self.units_per_em)
+    }
+
+    fn flags(&self) -> u32 {
+        // A composite font uses glyph ids rather than a standard
+        // encoding, so it is symbolic.
+        let mut flags = if self.composite { 1 << 2 } else { 1 << 5 };
+        if self.fixed_pitch {
+            flags |= 1;
+        }
+        if self.italic_angle != 0.0 {
+            flags |= 1 << 6;
+        }
+        flags
+    }
+
+    /// Get the characters actually shown for `chars` in this font,
+    /// with their glyph ids.
+    fn get_glyph_ids(&self, chars: &BTreeSet<char>) -> BTreeMap<char, u16> {
+        let encoding = self.get_encoding();
+        chars
+            .iter()
+            .filter_map(|&ch| {
+                if self.composite {
+                    Some(ch)
+                } else {
+                    // Characters that are not in the encoding are never
+                    // shown, FontRegistry::encode_text rejects them.
+                    encoding.decode_char(encoding.encode_char(ch)?)
+                }
+            })
+            .map(|ch| (ch, self.get_glyph_id(ch)))
+            .collect()
+    }
+
+    fn write_simple_font(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        base_name: &str,
+        descriptor_id: usize,
+        to_unicode_id: usize,
+    ) -> Result<usize> {
+        pdf.write_new_object(|font_object_id, output| {
+            write!(
+                output,
+                "<< /Type /Font /Subtype /TrueType /BaseFont /{}\n   \
+                 /FirstChar 32 /LastChar 255\n   \
+                 /Widths [",
+                base_name,
+            )?;
+            for code in 32..=255 {
+                write!(
+                    output,
+                    " {}",
+                    self.metrics.get_width(code).unwrap_or(0)
+                )?;
+            }
+            writeln!(
+                output,
+                " ]\n   \
+                 /FontDescriptor {} 0 R\n   \
+                 /Encoding /{}\n   \
+                 /ToUnicode {} 0 R\n\
+                 >>",
+                descriptor_id,
+                self.get_encoding().get_name(),
+                to_unicode_id,
+            )?;
+            Ok(font_object_id)
+        })
+    }
+
+    fn write_composite_font(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        base_name: &str,
+        descriptor_id: usize,
+        to_unicode_id: usize,
+        glyphs: &BTreeSet<u16>,
+    ) -> Result<usize> {
+        let cid_font_id = pdf.write_new_object(|cid_font_id, output| {
+            write!(
+                output,
+                "<< /Type /Font /Subtype /CIDFontType2 /BaseFont /{}\n   \
+                 /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) \
+                 /Supplement 0 >>\n   \
+                 /FontDescriptor {} 0 R\n   \
+                 /CIDToGIDMap /Identity\n   \
+                 /W [",
+                base_name, descriptor_id,
+            )?;
+            for &gid in glyphs {
+                write!(output, " {} [ {} ]", gid, self.get_glyph_width(gid))?;
+            }
+            writeln!(output, " ]\n>>")?;
+            Ok(cid_font_id)
+        })?;
+        pdf.write_new_object(|font_object_id, output| {
+            writeln!(
+                output,
+                "<< /Type /Font /Subtype /Type0 /BaseFont /{}-{}\n   \
+                 /Encoding /{}\n   \
+                 /DescendantFonts [ {} 0 R ]\n   \
+                 /ToUnicode {} 0 R\n\
+                 >>",
+                base_name,
+                self.get_encoding().get_name(),
+                self.get_encoding().get_name(),
+                cid_font_id,
+                to_unicode_id,
+            )?;
+            Ok(font_object_id)
+        })
+    }
+
+    /// Get the glyph id for a character, or 0 (the `.notdef` glyph) if
+    /// the character is not in the font.
+    fn get_glyph_id(&self, ch: char) -> u16 {
+        self.cmap.get(&ch).cloned().unwrap_or(0)
+    }
+}
+
+impl FontSource for TrueTypeFont {
+    /// Write the font, with a subset of the font program containing
+    /// only the glyphs for `chars`.
+    fn write_object(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        chars: &BTreeSet<char>,
+    ) -> Result<usize> {
+        let cmap = self.get_glyph_ids(chars);
+        let mut glyphs = cmap.values().cloned().collect::<BTreeSet<_>>();
+        glyphs.insert(0);
+        let subset_cmap = if self.composite { None } else { Some(&cmap) };
+        let (data, all_glyphs) = subset(&self.data, &glyphs, subset_cmap)?;
+        let base_name = format!("{}+{}", subset_tag(&glyphs), self.name);
+        let file_id =
+            pdf.write_stream(&format!("/Length1 {}", data.len()), &data)?;
+        // The CIDs of a composite font are the glyph ids, and PDF/A-1
+        // needs a CIDSet with all of them for a subset.
+        let cid_set = if self.composite {
+            let id = pdf.write_stream("", &cid_set(&all_glyphs))?;
+            format!("\n   /CIDSet {} 0 R", id)
+        } else {
+            String::new()
+        };
+        let descriptor_id =
+            pdf.write_new_object(|descriptor_id, output| {
+                writeln!(
+                    output,
+                    "<< /Type /FontDescriptor\n   \
+                     /FontName /{name}\n   \
+                     /Flags {flags}\n   \
+                     /FontBBox [ {} {} {} {} ]\n   \
+                     /ItalicAngle {italic}\n   \
+                     /Ascent {ascent}\n   \
+                     /Descent {descent}\n   \
+                     /CapHeight {cap_height}\n   \
+                     /StemV {stem_v}\n   \
+                     /FontFile2 {file_id} 0 R{cid_set}\n\
+                     >>",
+                    self.scale(self.bbox[0]),
+                    self.scale(self.bbox[1]),
+                    self.scale(self.bbox[2]),
+                    self.scale(self.bbox[3]),
+                    name = base_name,
+                    flags = self.flags(),
+                    italic = self.italic_angle,
+                    ascent = self.scale(self.ascent),
+                    descent = self.scale(self.descent),
+                    cap_height = self.scale(self.cap_height),
+                    // There is no stem width in a TrueType font, so guess
+                    // one from the weight class.
+                    stem_v = 50 + (u32::from(self.weight) / 65).pow(2),
+                    file_id = file_id,
+                    cid_set = cid_set,
+                )?;
+                Ok(descriptor_id)
+            })?;
+        if self.composite {
+            // The codes of a composite font are the glyph ids.
+            let mut codes = BTreeMap::new();
+            for (&ch, &gid) in &cmap {
+                if gid != 0 {
+                    codes.entry(gid).or_insert(ch);
+                }
+            }
+            let to_unicode_id = write_to_unicode(pdf, &codes, true)?;
+            self.write_composite_font(
+                pdf,
+                &base_name,
+                descriptor_id,
+                to_unicode_id,
+                &glyphs,
+            )
+        } else {
+            let codes = simple_font_codes(self.get_encoding(), chars);
+            let to_unicode_id = write_to_unicode(pdf, &codes, false)?;
+            self.write_simple_font(
+                pdf,
+                &base_name,
+                descriptor_id,
+                to_unicode_id,
+            )
+        }
+    }
+
+    /// The name has a hash of the font data appended, so different
+    /// fonts with the same PostScript name are kept apart.  The name
+    /// of a composite font also has the encoding name appended, to
+    /// differ from the same font used as a simple font.
+    fn pdf_name(&self) -> String {
+        let name = format!("{}-{:016X}", self.name, self.hash);
+        if self.composite {
+            format!("{}-{}", name, self.get_encoding().get_name())
+        } else {
+            name
+        }
+    }
+
+    /// The encoding is WinAnsiEncoding for a simple font, and
+    /// Identity-H for a composite font.
+    fn get_encoding(&self) -> &'static Encoding {
+        if self.composite {
+            &IDENTITY_H_ENCODING
+        } else {
+            &WIN_ANSI_ENCODING
+        }
+    }
+
+    /// A composite font can show every character that has a glyph in
+    /// the font.
+    fn can_show(&self, ch: char) -> bool {
+        if self.composite {
+            self.cmap.contains_key(&ch)
+        } else {
+            self.get_encoding().encode_char(ch).is_some()
+        }
+    }
+
+    fn is_embedded(&self) -> bool {
+        true
+    }
+
+    /// A composite font shows text as a hex string of two-byte glyph
+    /// ids.
+    fn pdf_string(&self, text: &str) -> Vec<u8> {
+        if !self.composite {
+            let mut result = vec![b'('];
+            result.extend(self.get_encoding().encode_string(text));
+            result.push(b')');
+            return result;
+        }
+        let mut result = Vec::with_capacity(4 * text.len() + 2);
+        result.push(b'<');
+        for ch in text.chars() {
+            write!(result, "{:04X}", self.get_glyph_id(ch))
+                .expect("Write to Vec");
+        }
+        result.push(b'>');
+        result
+    }
+
+    fn get_width(&self, size: f32, text: &str) -> f32 {
+        size * self.get_width_raw(text) as f32 / 1000.0
+    }
+
+    fn get_width_raw(&self, text: &str) -> u32 {
+        if self.composite {
+            return text.chars().fold(0, |result, ch| {
+                result
+                    + u32::from(self.get_glyph_width(self.get_glyph_id(ch)))
+            });
+        }
+        text.chars().fold(0, |result, ch| {
+            result
+                + u32::from(
+                    self.get_encoding()
+                        .encode_char(ch)
+                        .and_then(|code| self.metrics.get_width(code))
+                        .unwrap_or(100),
+                )
+        })
+    }
+
+    fn get_metrics(&self) -> FontMetrics {
+        self.metrics.clone()
+    }
+}
+
+impl fmt::Debug for TrueTypeFont {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TrueTypeFont")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The table directory of a TrueType font.
+pub(crate) struct Tables<'a> {
+    data: &'a [u8],
+    tables: BTreeMap<[u8; 4], (usize, usize)>,
+}
+
+impl<'a> Tables<'a> {
+    pub(crate) fn parse(data: &'a [u8]) -> Result<Tables<'a>> {
+        match read_u32(data, 0)? {
+            0x0001_0000 | 0x7472_7565 => (), // 1.0 or 'true'
+            0x4F54_544F => {
+                return Err(invalid(
+                    "CFF-based OpenType fonts are not supported",
+                ))
+            }
+            0x7474_6366 => {
+                return Err(invalid("TrueType collections are not supported"))
+            }
+            _ => return Err(invalid("Not a TrueType font")),
+        }
+        let num_tables = usize::from(read_u16(data, 4)?);
+        let mut tables = BTreeMap::new();
+        for i in 0..num_tables {
+            let record = 12 + 16 * i;
+            let tag = slice(data, record, 4)?;
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            slice(data, offset, length)?;
+            tables.insert([tag[0], tag[1], tag[2], tag[3]], (offset, length));
+        }
+        if !tables.contains_key(b"glyf") {
+            return Err(invalid("Font has no TrueType outlines"));
+        }
+        Ok(Tables { data, tables })
+    }
+
+    pub(crate) fn get(&self, tag: &[u8; 4]) -> Result<&'a [u8]> {
+        match self.tables.get(tag) {
+            Some(&(offset, length)) => slice(self.data, offset, length),
+            None => Err(invalid(&format!(
+                "Missing {} table",
+                String::from_utf8_lossy(tag)
+            ))),
+        }
+    }
+}
+
+/// Find the PostScript name (name id 6) in a name table.
+fn parse_name(name: &[u8]) -> Option<String> {
+    let count = usize::from(read_u16(name, 2).ok()?);
+    let storage = usize::from(read_u16(name, 4).ok()?);
+    for i in 0..count {
+        let record = 6 + 12 * i;
+        let platform = read_u16(name, record).ok()?;
+        let encoding = read_u16(name, record + 2).ok()?;
+        if read_u16(name, record + 6).ok()? != 6 {
+            continue;
+        }
+        let length = usize::from(read_u16(name, record + 8).ok()?);
+        let offset = usize::from(read_u16(name, record + 10).ok()?);
+        let raw = slice(name, storage + offset, length).ok()?;
+        let value = match (platform, encoding) {
+            (3, 1) | (0, _) => String::from_utf16_lossy(
+                &raw.chunks(2)
+                    .map(|c| Some(u16::from_be_bytes([c[0], *c.get(1)?])))
+                    .collect::<Option<Vec<_>>>()?,
+            ),
+            (1, 0) => raw.iter().map(|&b| char::from(b)).collect(),
+            _ => continue,
+        };
+        // The name is used as a pdf name, so keep it to safe characters.
+        let value = value
+            .chars()
+            .filter(|c| c.is_ascii_graphic() && !"[](){}<>/%#".contains(*c))
+            .collect::<String>();
+        if !value.is_empty() {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Read the mapping from unicode characters to glyph ids from a cmap
+/// table, for a font with `num_glyphs` glyphs.
+pub(crate) fn parse_cmap(
+    cmap: &[u8],
+    num_glyphs: usize,
+) -> Result<BTreeMap<char, u16>> {
+    let num_tables = usize::from(read_u16(cmap, 2)?);
+    let mut best = None;
+    for i in 0..num_tables {
+        let record = 4 + 8 * i;
+        let platform = read_u16(cmap, record)?;
+        let encoding = read_u16(cmap, record + 2)?;
+        let offset = read_u32(cmap, record + 4)? as usize;
+        let format = read_u16(cmap, offset)?;
+        let rank = match (platform, encoding, format) {
+            (3, 10, 12) | (0, _, 12) => 2,
+            (3, 1, 4) | (0, _, 4) => 1,
+            _ => continue,
+        };
+        if best.map(|(r, _)| rank > r).unwrap_or(true) {
+            best = Some((rank, offset));
+        }
+    }
+    let offset = match best {
+        Some((_, offset)) => offset,
+        None => return Err(invalid("No unicode cmap in font")),
+    };
+    let mut result = BTreeMap::new();
+    let mut insert = |code: u32, gid: u32| {
+        if let Some(ch) = char::from_u32(code) {
+            if gid != 0 && (gid as usize) < num_glyphs {
+                result.entry(ch).or_insert(gid as u16);
+            }
+        }
+    };
+    if read_u16(cmap, offset)? == 12 {
+        let groups = read_u32(cmap, offset + 12)? as usize;
+        for i in 0..groups {
+            let group = offset + 16 + 12 * i;
+            let start = read_u32(cmap, group)?;
+            let end = read_u32(cmap, group + 4)?;
+            let start_gid = read_u32(cmap, group + 8)?;
+            if end < start || end > 0x10_FFFF {
+                return Err(invalid("Bad group in cmap format 12"));
+            }
+            // A group can not map more characters than there are glyphs
+            // after its first glyph, so a broken cmap can not map every
+            // code point.
+            let glyphs_left = (num_glyphs as u32).saturating_sub(start_gid);
+            if glyphs_left == 0 {
+                continue;
+            }
+            let end = end.min(start + (glyphs_left - 1));
+            for code in start..=end {
+                insert(code, start_gid + (code - start));
+            }
+        }
+    } else {
+        let seg_count = usize::from(read_u16(cmap, offset + 6)? / 2);
+        let ends = offset + 14;
+        let starts = ends + 2 * seg_count + 2;
+        let deltas = starts + 2 * seg_count;
+        let range_offsets = deltas + 2 * seg_count;
+        for seg in 0..seg_count {
+            let end = read_u16(cmap, ends + 2 * seg)?;
+            let start = read_u16(cmap, starts + 2 * seg)?;
+            let delta = read_u16(cmap, deltas + 2 * seg)?;
+            let range_pos = range_offsets + 2 * seg;
+            let range_offset = usize::from(read_u16(cmap, range_pos)?);
+            if end < start {
+                continue;
+            }
+            for code in start..=end {
+                if code == 0xFFFF {
+                    break;
+                }
+                let gid = if range_offset == 0 {
+                    code.wrapping_add(delta)
+                } else {
+                    let pos = range_pos
+                        + range_offset
+                        + 2 * usize::from(code - start);
+                    match read_u16(cmap, pos)? {
+                        0 => 0,
+                        gid => gid.wrapping_add(delta),
+                    }
+                };
+                insert(u32::from(code), u32::from(gid));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Get the data of a CIDSet stream, with a bit for each CID from 0,
+/// the high bit of each byte first, that is set for the `cids` given.
+fn cid_set(cids: &BTreeSet<u16>) -> Vec<u8> {
+    let len = cids.iter().next_back().map_or(0, |&cid| cid / 8 + 1);
+    let mut data = vec![0; usize::from(len)];
+    for &cid in cids {
+        data[usize::from(cid / 8)] |= 0x80 >> (cid % 8);
+    }
+    data
+}
+
+pub(crate) fn invalid(message: &str) -> PdfError {
+    PdfError::InvalidArgument(message.to_string())
+}
+
+pub(crate) fn slice(data: &[u8], pos: usize, len: usize) -> Result<&[u8]> {
+    pos.checked_add(len)
+        .and_then(|end| data.get(pos..end))
+        .ok_or_else(|| invalid("Unexpected end of font data"))
+}
+
+pub(crate) fn read_u16(data: &[u8], pos: usize) -> Result<u16> {
+    slice(data, pos, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+pub(crate) fn read_i16(data: &[u8], pos: usize) -> Result<i16> {
+    read_u16(data, pos).map(|v| v as i16)
+}
+
+pub(crate) fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    slice(data, pos, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[test]
+fn test_cid_set() {
+    let cids = [0, 3, 8, 17].iter().cloned().collect();
+    assert_eq!(vec![0b1001_0000, 0b1000_0000, 0b0100_0000], cid_set(&cids));
+    assert_eq!(Vec::<u8>::new(), cid_set(&BTreeSet::new()));
+}
+
+#[test]
+fn test_parse_cmap() {
+    let data = include_bytes!("../data/PdfCanvasTest.ttf");
+    let tables = Tables::parse(data).unwrap();
+    let cmap = tables.get(b"cmap").unwrap();
+    let expected = [(' ', 1), ('A', 3), ('B', 2), ('Ä', 5), ('˙', 4)];
+    // Format 12 is preferred, for characters outside the BMP.
+    let mut full = expected.iter().cloned().collect::<BTreeMap<_, _>>();
+    full.insert('😀', 6);
+    assert_eq!(full, parse_cmap(cmap, 7).unwrap());
+    // Use only the first subtable, of format 4.
+    let mut bmp = cmap.to_vec();
+    bmp[2..4].copy_from_slice(&1u16.to_be_bytes());
+    assert_eq!(
+        4,
+        read_u16(&bmp, read_u32(&bmp, 8).unwrap() as usize).unwrap()
+    );
+    let bmp_only = expected.iter().cloned().collect::<BTreeMap<_, _>>();
+    assert_eq!(bmp_only, parse_cmap(&bmp, 7).unwrap());
+    // Glyph ids past the last glyph are ignored.
+    assert_eq!(3, parse_cmap(&bmp, 4).unwrap().len());
+}
+
+#[test]
+fn test_parse_cmap_limits_groups() {
+    let mut cmap = Vec::new();
+    for value in &[0u16, 1, 3, 10] {
+        cmap.extend_from_slice(&value.to_be_bytes());
+    }
+    cmap.extend_from_slice(&12u32.to_be_bytes());
+    // A format 12 subtable with one group for every code point.
+    for value in &[12u32 << 16, 28, 0, 1, 0, 0x10_FFFF, 1] {
+        cmap.extend_from_slice(&value.to_be_bytes());
+    }
+    let result = parse_cmap(&cmap, 7).unwrap();
+    let expected = (0..6)
+        .map(|i| (char::from(i as u8), i + 1))
+        .collect::<BTreeMap<_, u16>>();
+    assert_eq!(Some('\u{5}'), result.keys().next_back().cloned());
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn test_truetype_font() {
+    let data = include_bytes!("../data/PdfCanvasTest.ttf");
+    let font = TrueTypeFont::from_bytes(data).unwrap();
+    let name = format!("PdfCanvasTest-Regular-{:016X}", fnv_hash(*data));
+    assert_eq!(name, font.pdf_name());
+    assert_eq!(1000, font.units_per_em);
+    assert_eq!([-50, -100, 600, 900], font.bbox);
+    assert_eq!(
+        (800, -200, 700),
+        (font.ascent, font.descent, font.cap_height)
+    );
+    // The last glyph has the advance of the last horizontal metric.
+    assert_eq!(vec![500, 250, 550, 600, 333, 600, 600], font.advances);
+    assert_eq!(600 + 550 + 250 + 600, font.get_width_raw("AB Ä"));
+    assert_eq!(Some(600), font.get_metrics().get_width(b'A'));
+    assert_eq!(Some(500), font.get_metrics().get_width(b'C'));
+    assert!(font.can_show('Ä') && !font.can_show('😀'));
+    assert_eq!(b"(AB)".to_vec(), font.pdf_string("AB"));
+
+    let font = font.composite();
+    assert_eq!(format!("{}-Identity-H", name), font.pdf_name());
+    assert!(font.can_show('😀') && !font.can_show('C'));
+    assert_eq!(600 + 600, font.get_width_raw("A😀"));
+    assert_eq!(b"<00030006>".to_vec(), font.pdf_string("A😀"));
+    assert!(TrueTypeFont::from_bytes(&data[..100]).is_err());
+}
+
+#[test]
+fn test_truetype_font_name_depends_on_data() {
+    let data = include_bytes!("../data/PdfCanvasTest.ttf");
+    let font = TrueTypeFont::from_bytes(data).unwrap();
+    let same = TrueTypeFont::from_bytes(data).unwrap();
+    assert_eq!(font.pdf_name(), same.pdf_name());
+    // Another font with the same PostScript name.
+    let mut other = data.to_vec();
+    other[16] ^= 1; // the checksum of the first table
+    let other = TrueTypeFont::from_bytes(&other).unwrap();
+    assert_eq!(font.name, other.name);
+    assert_ne!(font.pdf_name(), other.pdf_name());
+}
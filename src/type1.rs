@@ -0,0 +1,548 @@
+use crate::afm::{self, Afm};
+use crate::encoding::{Encoding, WIN_ANSI_ENCODING};
+use crate::error::data_error;
+use crate::fontmetrics::FontMetrics;
+use crate::fontsource::FontSource;
+use crate::subset::fnv_hash;
+use crate::tounicode::{simple_font_codes, write_to_unicode};
+use crate::truetype::invalid;
+use crate::{Pdf, PdfOutput, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A Type1 font, with metrics from an AFM (Adobe Font Metrics) file.
+///
+/// The font program, from a `.pfb` or `.pfa` file, can be added with
+/// [with_program](#method.with_program) to embed it in the pdf file.
+/// Without a font program, the font is only referenced by name, so
+/// it needs to be available to the viewer of the pdf file.
+///
+/// Text is shown in the font using WinAnsiEncoding, so the font
+/// should have glyphs named as in the standard latin character set.
+/// A symbolic font, with `EncodingScheme FontSpecific` in the AFM
+/// file, is instead used with its own encoding, as given by the
+/// character codes in the AFM file.
+///
+/// Cloning a Type1Font is cheap, the font program is shared.
+///
+/// # Example
+///
+/// ```no_run
+/// # use pdf_canvas::{Pdf, Type1Font};
+/// let font = Type1Font::from_afm_file("fonts/texgyreheros-regular.afm")
+///     .and_then(|font| {
+///         font.with_program_file("fonts/texgyreheros-regular.pfb")
+///     })
+///     .expect("Read font files");
+/// let mut document = Pdf::create("type1.pdf").unwrap();
+/// document.render_page(180.0, 240.0, |canvas| {
+///     canvas.left_text(10.0, 200.0, font.clone(), 14.0, "Hello World!")
+/// }).unwrap();
+/// document.finish().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Type1Font {
+    name: String,
+    hash: u64,
+    metrics: FontMetrics,
+    encoding: Arc<Encoding>,
+    symbolic: bool,
+    fixed_pitch: bool,
+    stem_v: f32,
+    program: Option<Arc<Type1Program>>,
+}
+
+/// A Type1 font program, as embedded in a pdf file.
+struct Type1Program {
+    /// The clear-text portion, the binary encrypted portion and the
+    /// fixed-content portion of the font, concatenated.
+    data: Vec<u8>,
+    lengths: [usize; 3],
+}
+
+impl Type1Font {
+    /// Read a Type1 font from an AFM file.
+    pub fn from_afm_file<P: AsRef<Path>>(path: P) -> Result<Type1Font> {
+        Type1Font::from_afm(File::open(path)?)
+    }
+
+    /// Create a Type1 font from the contents of an AFM file.
+    ///
+    /// # Example
+    /// ```
+    /// # use pdf_canvas::{Type1Font, FontSource};
+    /// let afm = "StartFontMetrics 4.1\n\
+    ///            FontName Example-Regular\n\
+    ///            StartCharMetrics 1\n\
+    ///            C 65 ; WX 600 ; N A ; B 10 0 590 700 ;\n\
+    ///            EndCharMetrics\n\
+    ///            EndFontMetrics\n";
+    /// let font = Type1Font::from_afm(afm.as_bytes()).unwrap();
+    /// assert!(font.pdf_name().starts_with("Example-Regular-"));
+    /// assert_eq!(1200, font.get_width_raw("AA"));
+    /// ```
+    pub fn from_afm<R: Read>(mut source: R) -> Result<Type1Font> {
+        let mut data = Vec::new();
+        source.read_to_end(&mut data)?;
+        let afm = afm::parse(&data[..]).map_err(data_error)?;
+        let name = afm
+            .font_name
+            .clone()
+            .ok_or_else(|| invalid("No FontName in AFM file"))?;
+        let symbolic = afm.encoding_scheme.as_deref() == Some("FontSpecific");
+        let (metrics, encoding) = if symbolic {
+            let metrics =
+                FontMetrics::from_afm(&afm, |c| u8::try_from(c.code).ok());
+            let glyphs = afm.chars.iter().filter_map(|c| {
+                Some((u8::try_from(c.code).ok()?, c.name.as_deref()?))
+            });
+            (metrics, Encoding::font_specific("FontSpecific", glyphs))
+        } else {
+            let metrics = FontMetrics::from_afm(&afm, |c| {
+                WIN_ANSI_ENCODING.get_code(c.name.as_ref()?)
+            });
+            (metrics, WIN_ANSI_ENCODING.clone())
+        };
+        Ok(Type1Font {
+            name,
+            hash: fnv_hash(data),
+            metrics,
+            encoding: Arc::new(encoding),
+            symbolic,
+            fixed_pitch: afm.is_fixed_pitch,
+            stem_v: stem_v(&afm),
+            program: None,
+        })
+    }
+
+    /// Embed the font program from a `.pfb` or `.pfa` file.
+    pub fn with_program_file<P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> Result<Type1Font> {
+        self.with_program(&fs::read(path)?)
+    }
+
+    /// Embed a font program, the contents of a `.pfb` (binary) or
+    /// `.pfa` (ascii) file.
+    pub fn with_program(mut self, data: &[u8]) -> Result<Type1Font> {
+        let parts = if data.first() == Some(&0x80) {
+            parse_pfb(data)?
+        } else {
+            parse_pfa(data)?
+        };
+        self.hash = fnv_hash(
+            self.hash
+                .to_be_bytes()
+                .into_iter()
+                .chain(data.iter().copied()),
+        );
+        self.program = Some(Arc::new(Type1Program {
+            lengths: [parts[0].len(), parts[1].len(), parts[2].len()],
+            data: parts.concat(),
+        }));
+        Ok(self)
+    }
+
+    fn flags(&self) -> u32 {
+        let mut flags = if self.symbolic { 1 << 2 } else { 1 << 5 };
+        if self.fixed_pitch {
+            flags |= 1;
+        }
+        if self.metrics.get_italic_angle() != 0.0 {
+            flags |= 1 << 6;
+        }
+        flags
+    }
+
+    fn write_descriptor(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+    ) -> Result<usize> {
+        let file_id = match self.program {
+            Some(ref program) => Some(program.write_object(pdf)?),
+            None => None,
+        };
+        let m = &self.metrics;
+        let bbox = m.get_font_bbox().unwrap_or([0.0; 4]);
+        let ascent = m.get_ascender().unwrap_or(bbox[3]);
+        pdf.write_new_object(|descriptor_id, output| {
+            write!(
+                output,
+                "<< /Type /FontDescriptor\n   \
+                 /FontName /{name}\n   \
+                 /Flags {flags}\n   \
+                 /FontBBox [ {} {} {} {} ]\n   \
+                 /ItalicAngle {italic}\n   \
+                 /Ascent {ascent}\n   \
+                 /Descent {descent}\n   \
+                 /CapHeight {cap_height}\n   \
+                 /StemV {stem_v}\n",
+                bbox[0],
+                bbox[1],
+                bbox[2],
+                bbox[3],
+                name = self.name,
+                flags = self.flags(),
+                italic = m.get_italic_angle(),
+                ascent = ascent,
+                descent = m.get_descender().unwrap_or(bbox[1]),
+                cap_height = m.get_cap_height().unwrap_or(ascent),
+                stem_v = self.stem_v,
+            )?;
+            if let Some(x_height) = m.get_x_height() {
+                writeln!(output, "   /XHeight {}", x_height)?;
+            }
+            if let Some(file_id) = file_id {
+                writeln!(output, "   /FontFile {} 0 R", file_id)?;
+            }
+            writeln!(output, ">>")?;
+            Ok(descriptor_id)
+        })
+    }
+}
+
+impl Type1Program {
+    /// Write the font program as a FontFile stream.
+    fn write_object(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+    ) -> Result<usize> {
+        pdf.write_stream(
+            &format!(
+                "/Length1 {} /Length2 {} /Length3 {}",
+                self.lengths[0], self.lengths[1], self.lengths[2],
+            ),
+            &self.data,
+        )
+    }
+}
+
+impl FontSource for Type1Font {
+    fn write_object(
+        &self,
+        pdf: &mut Pdf<dyn PdfOutput + '_>,
+        chars: &BTreeSet<char>,
+    ) -> Result<usize> {
+        let encoding = self.get_encoding();
+        let codes = simple_font_codes(encoding, chars);
+        let to_unicode_id = write_to_unicode(pdf, &codes, false)?;
+        let descriptor_id = self.write_descriptor(pdf)?;
+        // A symbolic font may use codes below 32 for some glyphs.
+        let first_char = codes.keys().next().map_or(32, |&code| code.min(32));
+        pdf.write_new_object(|font_object_id, output| {
+            write!(
+                output,
+                "<< /Type /Font /Subtype /Type1 /BaseFont /{}\n   \
+                 /FirstChar {} /LastChar 255\n   \
+                 /Widths [",
+                self.name, first_char,
+            )?;
+            for code in first_char as u8..=255 {
+                write!(
+                    output,
+                    " {}",
+                    self.metrics.get_width(code).unwrap_or(0)
+                )?;
+            }
+            writeln!(
+                output,
+                " ]\n   \
+                 /FontDescriptor {} 0 R",
+                descriptor_id,
+            )?;
+            // A symbolic font uses the built-in encoding of its program.
+            if !self.symbolic {
+                writeln!(output, "   /Encoding {}", encoding.pdf_object())?;
+            }
+            writeln!(output, "   /ToUnicode {} 0 R\n>>", to_unicode_id)?;
+            Ok(font_object_id)
+        })
+    }
+
+    /// The name has a hash of the AFM data and font program appended,
+    /// so different fonts with the same FontName are kept apart.
+    fn pdf_name(&self) -> String {
+        format!("{}-{:016X}", self.name, self.hash)
+    }
+
+    /// The encoding is WinAnsiEncoding, or the built-in encoding of a
+    /// symbolic font.
+    fn get_encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    /// The font is embedded if it has a font program.
+    fn is_embedded(&self) -> bool {
+        self.program.is_some()
+    }
+
+    fn get_width(&self, size: f32, text: &str) -> f32 {
+        size * self.get_width_raw(text) as f32 / 1000.0
+    }
+
+    fn get_width_raw(&self, text: &str) -> u32 {
+        let encoding = self.get_encoding();
+        text.chars().fold(0, |result, ch| {
+            let code = encoding.encode_char(ch).unwrap_or(b'?');
+            result + u32::from(self.metrics.get_width(code).unwrap_or(0))
+        })
+    }
+
+    fn get_kerning(&self, left: char, right: char) -> i32 {
+        let encoding = self.get_encoding();
+        let code = |ch| encoding.encode_char(ch).unwrap_or(b'?');
+        self.metrics.get_kerning(code(left), code(right))
+    }
+
+    fn get_metrics(&self) -> FontMetrics {
+        self.metrics.clone()
+    }
+}
+
+impl fmt::Debug for Type1Font {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Type1Font")
+            .field("name", &self.name)
+            .field("embedded", &self.program.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Get the stem width of a font, guessed from the font name unless
+/// the AFM file has it.
+fn stem_v(afm: &Afm) -> f32 {
+    afm.std_vw.unwrap_or_else(|| {
+        let name = afm.font_name.as_deref().unwrap_or("");
+        if name.contains("Bold") || name.contains("Black") {
+            140.0
+        } else {
+            80.0
+        }
+    })
+}
+
+/// Split a `.pfb` file into its clear-text, binary and fixed-content
+/// portions.
+///
+/// A pfb file is a sequence of segments, each with a header of 0x80, a
+/// type (1 for ascii, 2 for binary, 3 for end of file), and a
+/// little-endian four byte length.
+fn parse_pfb(mut data: &[u8]) -> Result<[Vec<u8>; 3]> {
+    let mut parts: [Vec<u8>; 3] = Default::default();
+    loop {
+        let (kind, length) = match data {
+            [0x80, 3, ..] => break,
+            [0x80, kind, a, b, c, d, ..] => {
+                (*kind, u32::from_le_bytes([*a, *b, *c, *d]) as usize)
+            }
+            _ => return Err(invalid("Bad segment header in pfb file")),
+        };
+        let segment = data
+            .get(6..6 + length)
+            .ok_or_else(|| invalid("Unexpected end of pfb file"))?;
+        let part = match kind {
+            1 if parts[1].is_empty() => 0,
+            2 if parts[2].is_empty() => 1,
+            1 => 2,
+            _ => return Err(invalid("Bad segment type in pfb file")),
+        };
+        parts[part].extend_from_slice(segment);
+        data = &data[6 + length..];
+    }
+    if parts[1].is_empty() {
+        return Err(invalid("No encrypted portion in pfb file"));
+    }
+    Ok(parts)
+}
+
+/// Split a `.pfa` file into its clear-text, binary and fixed-content
+/// portions.
+///
+/// The encrypted portion, starting after `eexec`, is hex-encoded in a
+/// pfa file, but should be binary in a pdf file.
+/// The fixed-content portion is 512 zeros followed by `cleartomark`.
+fn parse_pfa(data: &[u8]) -> Result<[Vec<u8>; 3]> {
+    if !data.starts_with(b"%!") {
+        return Err(invalid("Not a Type1 font program"));
+    }
+    let eexec = data
+        .windows(5)
+        .position(|w| w == b"eexec")
+        .ok_or_else(|| invalid("No encrypted portion in pfa file"))?;
+    let mut start = eexec + 5;
+    while data.get(start).is_some_and(|c| c.is_ascii_whitespace()) {
+        start += 1;
+    }
+    let mark = data
+        .windows(11)
+        .rposition(|w| w == b"cleartomark")
+        .filter(|&mark| mark > start)
+        .unwrap_or(data.len());
+    let mut end = mark;
+    let mut zeros = 0;
+    while end > start && zeros < 512 {
+        match data[end - 1] {
+            b'0' => zeros += 1,
+            c if c.is_ascii_whitespace() => (),
+            _ => break,
+        }
+        end -= 1;
+    }
+    let hex = data[start..end]
+        .iter()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(|&c| char::from(c).to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid("Bad hex data in pfa file"))?;
+    if hex.len() % 2 != 0 {
+        return Err(invalid("Bad hex data in pfa file"));
+    }
+    let binary = hex.chunks(2).map(|c| c[0] << 4 | c[1]).collect();
+    Ok([data[..start].to_vec(), binary, data[end..].to_vec()])
+}
+
+#[test]
+fn test_symbolic_afm() {
+    let afm = "StartFontMetrics 4.1\n\
+               FontName Example-Symbols\n\
+               EncodingScheme FontSpecific\n\
+               StartCharMetrics 4\n\
+               C 32 ; WX 250 ; N space ;\n\
+               C 65 ; WX 500 ; N alpha ;\n\
+               C 66 ; WX 700 ; N a20 ;\n\
+               C -1 ; WX 900 ; N unencoded ;\n\
+               EndCharMetrics\n\
+               EndFontMetrics\n";
+    let font = Type1Font::from_afm(afm.as_bytes()).unwrap();
+    let encoding = font.get_encoding();
+    assert_eq!(Some(b'A'), encoding.encode_char('α'));
+    assert_eq!(Some(b'B'), encoding.encode_char('✔'));
+    assert_eq!(Some(b'B'), encoding.get_code("a20"));
+    assert_eq!(None, encoding.encode_char('A'));
+    assert_eq!(1450, font.get_width_raw("α ✔"));
+    assert_eq!(1 << 2, font.flags());
+
+    // Glyph names are used for a font with the standard encoding.
+    let afm = afm.replace("FontSpecific", "AdobeStandardEncoding");
+    let font = Type1Font::from_afm(afm.as_bytes()).unwrap();
+    assert_eq!(Some(b'A'), font.get_encoding().encode_char('A'));
+    assert_eq!(0, font.get_width_raw("A"));
+    assert_eq!(250, font.get_width_raw(" "));
+}
+
+#[test]
+fn test_type1_font_name_depends_on_data() {
+    let afm = "StartFontMetrics 4.1\n\
+               FontName Example\n\
+               StartCharMetrics 1\n\
+               C 65 ; WX 600 ; N A ;\n\
+               EndCharMetrics\n\
+               EndFontMetrics\n";
+    let name =
+        |afm: &str| Type1Font::from_afm(afm.as_bytes()).unwrap().pdf_name();
+    assert!(name(afm).starts_with("Example-"));
+    assert_eq!(name(afm), name(afm));
+    assert_ne!(name(afm), name(&afm.replace("600", "500")));
+
+    let pfa = |binary| {
+        format!("%!FontType1 eexec\n{}\n{}", binary, "0".repeat(512))
+    };
+    let font = Type1Font::from_afm(afm.as_bytes()).unwrap();
+    let embed = |pfa: String| {
+        font.clone()
+            .with_program(pfa.as_bytes())
+            .unwrap()
+            .pdf_name()
+    };
+    assert_ne!(font.pdf_name(), embed(pfa("01")));
+    assert_eq!(embed(pfa("01")), embed(pfa("01")));
+    assert_ne!(embed(pfa("01")), embed(pfa("02")));
+}
+
+#[test]
+fn test_parse_pfb() {
+    let segment = |kind: u8, data: &[u8]| {
+        let mut result = vec![0x80, kind];
+        result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        result.extend_from_slice(data);
+        result
+    };
+    let pfb = [
+        segment(1, b"%!FontType1 eexec\r"),
+        segment(2, &[1, 2, 3]),
+        segment(2, &[4, 5]),
+        segment(1, b"0000cleartomark\r"),
+        vec![0x80, 3],
+    ]
+    .concat();
+    let [clear, binary, fixed] = parse_pfb(&pfb).unwrap();
+    assert_eq!(b"%!FontType1 eexec\r", clear.as_slice());
+    assert_eq!(vec![1, 2, 3, 4, 5], binary);
+    assert_eq!(b"0000cleartomark\r", fixed.as_slice());
+
+    let program = Type1Font::from_afm(
+        "StartFontMetrics 4.1\nFontName Example\nEndFontMetrics\n".as_bytes(),
+    )
+    .and_then(|font| font.with_program(&pfb))
+    .unwrap()
+    .program
+    .unwrap();
+    assert_eq!([18, 5, 16], program.lengths);
+    assert_eq!(39, program.data.len());
+
+    let error = |pfb: &[u8]| parse_pfb(pfb).unwrap_err().to_string();
+    assert_eq!(
+        "Invalid argument: Unexpected end of pfb file",
+        error(&pfb[..pfb.len() - 10])
+    );
+    assert_eq!(
+        "Invalid argument: Bad segment header in pfb file",
+        error(&pfb[..pfb.len() - 2])
+    );
+    assert_eq!(
+        "Invalid argument: Bad segment header in pfb file",
+        error(&pfb[..3])
+    );
+    assert_eq!(
+        "Invalid argument: No encrypted portion in pfb file",
+        error(&[segment(1, b"%!"), vec![0x80, 3]].concat())
+    );
+    assert_eq!(
+        "Invalid argument: Bad segment type in pfb file",
+        error(&[segment(5, b"%!"), vec![0x80, 3]].concat())
+    );
+}
+
+#[test]
+fn test_parse_pfa() {
+    let fixed = format!("{}\ncleartomark\n", "0".repeat(512));
+    let pfa = format!("%!FontType1 eexec\n01 0203\nFF\n{}", fixed);
+    let [clear, binary, rest] = parse_pfa(pfa.as_bytes()).unwrap();
+    assert_eq!(b"%!FontType1 eexec\n", clear.as_slice());
+    assert_eq!(vec![1, 2, 3, 255], binary);
+    assert_eq!(fixed.as_bytes(), rest.as_slice());
+
+    let error =
+        |pfa: &str| parse_pfa(pfa.as_bytes()).unwrap_err().to_string();
+    assert_eq!(
+        "Invalid argument: Not a Type1 font program",
+        error("StartFontMetrics 4.1\n")
+    );
+    assert_eq!(
+        "Invalid argument: No encrypted portion in pfa file",
+        error("%!FontType1\n")
+    );
+    assert_eq!(
+        "Invalid argument: Bad hex data in pfa file",
+        error(&format!("%!FontType1 eexec\n01 0X\n{}", fixed))
+    );
+    assert_eq!(
+        "Invalid argument: Bad hex data in pfa file",
+        error(&format!("%!FontType1 eexec\n01 020\n{}", fixed))
+    );
+}
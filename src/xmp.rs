@@ -0,0 +1,312 @@
+//! XMP metadata, written as the `/Metadata` stream of a document.
+//!
+//! The packet has the same title, author, dates and so on as the
+//! document info dictionary, and any custom properties.
+use crate::{PdfA, PdfError, Result};
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use std::collections::BTreeMap;
+
+/// The namespaces used by the packet itself, by prefix.
+///
+/// The first three are for the packet structure, then come the
+/// namespaces of the standard properties, the PDF/A identification,
+/// and the PDF/A extension schemas.
+const STANDARD_NAMESPACES: [(&str, &str); 10] = [
+    ("x", "adobe:ns:meta/"),
+    ("rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#"),
+    ("xml", "http://www.w3.org/XML/1998/namespace"),
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("pdf", "http://ns.adobe.com/pdf/1.3/"),
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("pdfaid", "http://www.aiim.org/pdfa/ns/id/"),
+    ("pdfaExtension", "http://www.aiim.org/pdfa/ns/extension/"),
+    ("pdfaSchema", "http://www.aiim.org/pdfa/ns/schema#"),
+    ("pdfaProperty", "http://www.aiim.org/pdfa/ns/property#"),
+];
+
+/// Custom properties for the XMP metadata, by namespace uri.
+#[derive(Default)]
+pub(crate) struct CustomProperties {
+    namespaces: BTreeMap<String, Namespace>,
+}
+
+struct Namespace {
+    prefix: String,
+    properties: BTreeMap<String, String>,
+}
+
+impl CustomProperties {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.namespaces.is_empty()
+    }
+
+    /// Set the property `name` in the namespace `uri`, which is written
+    /// with `prefix`.
+    pub(crate) fn set(
+        &mut self,
+        uri: &str,
+        prefix: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        check_name(prefix)?;
+        check_name(name)?;
+        if let Some((reserved, _)) = STANDARD_NAMESPACES
+            .iter()
+            .find(|&&(p, u)| p == prefix || u == uri)
+        {
+            return Err(PdfError::InvalidArgument(format!(
+                "The XMP namespace {:?} is reserved",
+                reserved
+            )));
+        }
+        let taken = self
+            .namespaces
+            .iter()
+            .find(|(u, namespace)| *u != uri && namespace.prefix == prefix);
+        if let Some((other, _)) = taken {
+            return Err(PdfError::InvalidArgument(format!(
+                "The XMP prefix {:?} is already used for {:?}",
+                prefix, other
+            )));
+        }
+        let namespace = self
+            .namespaces
+            .entry(uri.to_string())
+            .or_insert_with(|| Namespace {
+                prefix: prefix.to_string(),
+                properties: BTreeMap::new(),
+            });
+        if namespace.prefix != prefix {
+            return Err(PdfError::InvalidArgument(format!(
+                "The XMP namespace {:?} already has the prefix {:?}",
+                uri, namespace.prefix
+            )));
+        }
+        namespace
+            .properties
+            .insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+/// Get the XMP packet for a document, with the entries of its
+/// document `info` dictionary, its dates, and `custom` properties.
+///
+/// For a PDF/A document, the packet also gets the PDF/A
+/// identification, and extension schemas for the custom properties.
+pub(crate) fn xmp_packet(
+    info: &BTreeMap<String, String>,
+    creation_date: &DateTime<FixedOffset>,
+    mod_date: &DateTime<FixedOffset>,
+    custom: &CustomProperties,
+    pdf_a: Option<PdfA>,
+) -> String {
+    let mut xmp = String::from(
+        "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+         <rdf:RDF xmlns:rdf=\"\
+         http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+    );
+    let mut properties = Vec::new();
+    for (key, value) in info {
+        let value = escape(value);
+        properties.push(match key.as_str() {
+            "Title" => format!(
+                "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}\
+                 </rdf:li></rdf:Alt></dc:title>",
+                value
+            ),
+            "Author" => format!(
+                "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq>\
+                 </dc:creator>",
+                value
+            ),
+            "Subject" => format!(
+                "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}\
+                 </rdf:li></rdf:Alt></dc:description>",
+                value
+            ),
+            "Keywords" => format!("<pdf:Keywords>{}</pdf:Keywords>", value),
+            "Creator" => {
+                format!("<xmp:CreatorTool>{}</xmp:CreatorTool>", value)
+            }
+            "Producer" => format!("<pdf:Producer>{}</pdf:Producer>", value),
+            _ => continue,
+        });
+    }
+    properties.push(format!(
+        "<xmp:CreateDate>{}</xmp:CreateDate>",
+        xmp_date(creation_date)
+    ));
+    properties.push(format!(
+        "<xmp:ModifyDate>{}</xmp:ModifyDate>",
+        xmp_date(mod_date)
+    ));
+    properties.push(format!(
+        "<xmp:MetadataDate>{}</xmp:MetadataDate>",
+        xmp_date(mod_date)
+    ));
+    let standard = STANDARD_NAMESPACES[3..6].iter().copied();
+    write_description(&mut xmp, standard, &properties);
+    if let Some(pdf_a) = pdf_a {
+        let identification = [
+            format!("<pdfaid:part>{}</pdfaid:part>", pdf_a.part()),
+            format!(
+                "<pdfaid:conformance>{}</pdfaid:conformance>",
+                pdf_a.conformance()
+            ),
+        ];
+        let namespaces = STANDARD_NAMESPACES[6..7].iter().copied();
+        write_description(&mut xmp, namespaces, &identification);
+        if !custom.is_empty() {
+            let namespaces = STANDARD_NAMESPACES[7..].iter().copied();
+            let schemas = [extension_schemas(custom)];
+            write_description(&mut xmp, namespaces, &schemas);
+        }
+    }
+    for (uri, namespace) in &custom.namespaces {
+        let properties = namespace
+            .properties
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "<{prefix}:{name}>{value}</{prefix}:{name}>",
+                    prefix = namespace.prefix,
+                    name = name,
+                    value = escape(value),
+                )
+            })
+            .collect::<Vec<_>>();
+        let namespaces = Some((namespace.prefix.as_str(), uri.as_str()));
+        write_description(&mut xmp, namespaces, &properties);
+    }
+    xmp.push_str(" </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>");
+    xmp
+}
+
+/// Get the `pdfaExtension:schemas` property, that describes the custom
+/// properties, as PDF/A requires for properties that are not in the
+/// standard schemas.
+fn extension_schemas(custom: &CustomProperties) -> String {
+    let mut schemas = String::from("<pdfaExtension:schemas><rdf:Bag>\n");
+    for (uri, namespace) in &custom.namespaces {
+        schemas.push_str(&format!(
+            "    <rdf:li rdf:parseType=\"Resource\">\n     \
+             <pdfaSchema:schema>{prefix} properties\
+             </pdfaSchema:schema>\n     \
+             <pdfaSchema:namespaceURI>{uri}</pdfaSchema:namespaceURI>\n     \
+             <pdfaSchema:prefix>{prefix}</pdfaSchema:prefix>\n     \
+             <pdfaSchema:property><rdf:Seq>\n",
+            prefix = namespace.prefix,
+            uri = escape(uri),
+        ));
+        for name in namespace.properties.keys() {
+            schemas.push_str(&format!(
+                "      <rdf:li rdf:parseType=\"Resource\">\n       \
+                 <pdfaProperty:name>{name}</pdfaProperty:name>\n       \
+                 <pdfaProperty:valueType>Text\
+                 </pdfaProperty:valueType>\n       \
+                 <pdfaProperty:category>external\
+                 </pdfaProperty:category>\n       \
+                 <pdfaProperty:description>{name}\
+                 </pdfaProperty:description>\n      \
+                 </rdf:li>\n",
+                name = name,
+            ));
+        }
+        schemas.push_str(
+            "     </rdf:Seq></pdfaSchema:property>\n    </rdf:li>\n",
+        );
+    }
+    schemas.push_str("   </rdf:Bag></pdfaExtension:schemas>");
+    schemas
+}
+
+/// Write an `rdf:Description` of the document, with the given
+/// namespaces and properties.
+fn write_description<'a, N>(
+    xmp: &mut String,
+    namespaces: N,
+    properties: &[String],
+) where
+    N: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    xmp.push_str("  <rdf:Description rdf:about=\"\"");
+    for (prefix, uri) in namespaces {
+        xmp.push_str(&format!("\n    xmlns:{}=\"{}\"", prefix, escape(uri)));
+    }
+    xmp.push_str(">\n");
+    for property in properties {
+        xmp.push_str(&format!("   {}\n", property));
+    }
+    xmp.push_str("  </rdf:Description>\n");
+}
+
+/// Format a date for XMP, in whole seconds like the info dictionary.
+fn xmp_date(date: &DateTime<FixedOffset>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// Check that `name` can be used as an XML name without a prefix.
+fn check_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let start = chars
+        .next()
+        .is_some_and(|ch| ch.is_alphabetic() || ch == '_');
+    let valid =
+        start && chars.all(|ch| ch.is_alphanumeric() || "_-.".contains(ch));
+    if valid {
+        Ok(())
+    } else {
+        Err(PdfError::InvalidArgument(format!(
+            "Bad XMP name {:?}",
+            name
+        )))
+    }
+}
+
+/// Escape `text` for XML content or attribute values.
+///
+/// Control characters can not be in XML, so they are left out.
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\t' | '\n' | '\r' => result.push(ch),
+            ch if ch.is_control() => (),
+            ch => result.push(ch),
+        }
+    }
+    result
+}
+
+#[test]
+fn test_escape_and_names() {
+    assert_eq!(
+        "a &lt;b&gt; &amp; &quot;c&quot;\n",
+        escape("a <b> & \"c\"\x07\n")
+    );
+    assert!(check_name("Invoice_No.2").is_ok());
+    assert!(check_name("2nd").is_err());
+    assert!(check_name("a b").is_err());
+    assert!(check_name("").is_err());
+}
+
+#[test]
+fn test_custom_namespaces() {
+    let mut custom = CustomProperties::default();
+    let uri = "http://example.com/ns/invoice/";
+    assert!(custom.set(uri, "inv", "Number", "17").is_ok());
+    assert!(custom.set(uri, "inv", "Due", "2020-03-01").is_ok());
+    assert!(custom.set(uri, "bill", "Number", "17").is_err());
+    assert!(custom.set("http://example.com/", "inv", "A", "").is_err());
+    assert!(custom.set("http://example.com/", "dc", "A", "").is_err());
+    assert!(custom
+        .set("http://purl.org/dc/elements/1.1/", "d", "A", "")
+        .is_err());
+}
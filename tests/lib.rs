@@ -25,3 +25,730 @@ fn metrics_for_long_string() {
         font.get_width(size, &long_text)
     ));
 }
+
+#[test]
+fn auto_encoding_uses_several_font_dicts() {
+    use pdf_canvas::Pdf;
+    // The Latin Extended-A letters in the font, mostly not in WinAnsi.
+    let text = "ĀāĂăĄąĆćČčĎďĐđĒēĖėĘęĚěĞğĢģĪīĮįİıĶķĹĺĻļĽľŁłŃńŅņŇňŌōŐőŒœ\
+                ŔŕŖŗŘřŚśŞşŠšŢţŤťŪūŮůŰűŲųŸŹźŻżŽž";
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_auto_encoding(true);
+    document
+        .render_page(600.0, 100.0, |canvas| {
+            canvas.left_text(10.0, 50.0, BuiltinFont::Times_Roman, 10.0, text)
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert_eq!(2, output.matches("/BaseFont /Times-Roman").count());
+    assert_eq!(2, output.matches("/Differences").count());
+    assert!(output.contains("/BaseEncoding /WinAnsiEncoding"));
+}
+
+#[test]
+fn png_alpha_becomes_soft_mask() {
+    use pdf_canvas::Pdf;
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[255, 0, 0, 255, 0, 0, 255, 128])
+            .unwrap();
+    }
+    let mut document = Pdf::create_with_buffer().unwrap();
+    let image = document.add_png(&png).unwrap();
+    assert_eq!((2, 1), (image.width(), image.height()));
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.draw_image(&image, 10.0, 10.0, 20.0, 10.0)
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert_eq!(2, output.matches("/Subtype /Image").count());
+    assert_eq!(1, output.matches("/SMask").count());
+    assert!(output.contains("/ColorSpace /DeviceRGB"));
+}
+
+#[test]
+fn template_is_written_once() {
+    use pdf_canvas::graphicsstate::Matrix;
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    let footer = document
+        .render_template([0.0, 0.0, 100.0, 20.0], |canvas| {
+            canvas.left_text(5.0, 5.0, BuiltinFont::Courier, 10.0, "Footer")
+        })
+        .unwrap();
+    for _ in 0..3 {
+        document
+            .render_page(100.0, 100.0, |canvas| {
+                canvas.draw_template(&footer, Matrix::translate(0.0, 10.0))
+            })
+            .unwrap();
+    }
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert_eq!(1, output.matches("(Footer) Tj").count());
+    assert_eq!(1, output.matches("/Subtype /Form").count());
+    assert_eq!(3, output.matches(&format!("{} Do", footer)).count());
+    assert_eq!(1, output.matches("/BaseFont /Courier").count());
+}
+
+#[test]
+fn compression_deflates_content_streams() {
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_compression(true);
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            // Enough text to be deflated, rather than stored.
+            for y in 0..20 {
+                let y = 5.0 * y as f32;
+                canvas.left_text(
+                    5.0,
+                    y,
+                    BuiltinFont::Courier,
+                    4.0,
+                    "Hidden",
+                )?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/Filter /FlateDecode"));
+    assert!(!output.contains("Hidden"));
+}
+
+#[test]
+fn object_streams_replace_xref_table() {
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_object_streams(true);
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.left_text(5.0, 5.0, BuiltinFont::Courier, 10.0, "Packed")
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("(Packed) Tj"));
+    assert_eq!(1, output.matches("/Type /ObjStm").count());
+    assert_eq!(1, output.matches("/Type /XRef").count());
+    assert!(!output.contains("/Type /Catalog"));
+    assert!(!output.contains("trailer"));
+}
+
+#[test]
+fn linearized_document_starts_with_first_page() {
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_linearized(true).unwrap();
+    for text in &["First", "Second"] {
+        document
+            .render_page(100.0, 100.0, |canvas| {
+                canvas.left_text(5.0, 5.0, BuiltinFont::Courier, 10.0, text)
+            })
+            .unwrap();
+    }
+    let bytes = document.finish().unwrap().into_inner();
+    let output = String::from_utf8_lossy(&bytes).into_owned();
+    let linearization = &output[..output.find(">>").unwrap()];
+    assert!(linearization.contains("<< /Linearized 1"));
+    assert!(linearization.contains(&format!("/L {} ", bytes.len())));
+    assert!(linearization.contains("/N 2 "));
+    assert!(
+        output.find("(First)").unwrap() < output.find("(Second)").unwrap()
+    );
+    assert!(
+        output.find("/Type /Catalog").unwrap()
+            < output.find("(First)").unwrap()
+    );
+}
+
+#[test]
+fn linearized_must_be_set_before_pages() {
+    use pdf_canvas::{Pdf, PdfError};
+    for linearized in [false, true] {
+        let mut document = Pdf::create_with_buffer().unwrap();
+        document.set_linearized(linearized).unwrap();
+        document
+            .render_page(100.0, 100.0, |canvas| {
+                canvas.left_text(5.0, 5.0, BuiltinFont::Courier, 10.0, "One")
+            })
+            .unwrap();
+        let result = document.set_linearized(!linearized);
+        assert!(matches!(result, Err(PdfError::Structure(_))));
+        // The setting is unchanged, so the document can be finished.
+        let output =
+            String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+                .into_owned();
+        assert_eq!(linearized, output.contains("/Linearized 1"));
+    }
+}
+
+#[test]
+fn document_can_be_written_to_any_writer() {
+    use pdf_canvas::Pdf;
+    // A Vec<u8> can be written to, but not seeked in.
+    let mut document = Pdf::new(Vec::new()).unwrap();
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.left_text(5.0, 5.0, BuiltinFont::Courier, 10.0, "Plain")
+        })
+        .unwrap();
+    let bytes = document.finish().unwrap();
+    let output = String::from_utf8_lossy(&bytes).into_owned();
+    assert!(output.contains("(Plain) Tj"));
+    let startxref = output.rsplit("startxref\n").next().unwrap();
+    let startxref = startxref.lines().next().unwrap().parse().unwrap();
+    assert!(bytes[startxref..].starts_with(b"xref\n"));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_document_is_written_per_page() {
+    use pdf_canvas::AsyncPdf;
+    let mut document = AsyncPdf::new(Vec::new()).unwrap();
+    document.set_title("Async");
+    for text in &["First", "Second"] {
+        document
+            .render_page(100.0, 100.0, |canvas| {
+                canvas.left_text(5.0, 5.0, BuiltinFont::Courier, 10.0, text)
+            })
+            .await
+            .unwrap();
+    }
+    let bytes = document.finish().await.unwrap();
+    let output = String::from_utf8_lossy(&bytes).into_owned();
+    assert!(output.starts_with("%PDF-1.7\n"));
+    assert!(output.contains("(First) Tj"));
+    assert!(output.contains("(Second) Tj"));
+    assert!(output.contains("/Title (Async)"));
+    let startxref = output.rsplit("startxref\n").next().unwrap();
+    let startxref = startxref.lines().next().unwrap().parse().unwrap();
+    assert!(bytes[startxref..].starts_with(b"xref\n"));
+}
+
+#[test]
+fn misuse_is_reported_as_pdf_errors() {
+    use pdf_canvas::{Pdf, PdfError};
+    let mut document = Pdf::create_with_buffer().unwrap();
+    let result = document.render_page(100.0, 100.0, |canvas| {
+        canvas.left_text(5.0, 5.0, BuiltinFont::Courier, 10.0, "Łódź")
+    });
+    assert!(matches!(result, Err(PdfError::Encoding(_))));
+    let result = document.render_page(100.0, 100.0, |canvas| {
+        canvas.line(0.0, 0.0, f32::NAN, 10.0)
+    });
+    assert!(matches!(result, Err(PdfError::InvalidArgument(_))));
+    let result = document.render_page(0.0, 100.0, |_| Ok(()));
+    assert!(matches!(result, Err(PdfError::InvalidArgument(_))));
+    let result = document.render_page(100.0, 100.0, |canvas| canvas.gsave());
+    assert!(matches!(result, Err(PdfError::Structure(_))));
+    let result =
+        document.render_page(100.0, 100.0, |canvas| canvas.grestore());
+    assert!(matches!(result, Err(PdfError::Structure(_))));
+    let result = document.add_jpeg(b"GIF89a");
+    assert!(matches!(result, Err(PdfError::InvalidArgument(_))));
+}
+
+#[test]
+fn font_from_another_document_is_an_error() {
+    use pdf_canvas::{Pdf, PdfError};
+    let mut other = Pdf::create_with_buffer().unwrap();
+    let mut fonts = Vec::new();
+    other
+        .render_page(100.0, 100.0, |canvas| {
+            fonts.push(canvas.get_font(BuiltinFont::Courier));
+            fonts.push(canvas.get_font(BuiltinFont::Helvetica));
+            Ok(())
+        })
+        .unwrap();
+    let mut document = Pdf::create_with_buffer().unwrap();
+    for font in &fonts {
+        // The first font has an index that is also used in this
+        // document, but for another font.
+        let result = document.render_page(100.0, 100.0, |canvas| {
+            canvas.get_font(BuiltinFont::Times_Roman);
+            canvas.text(|t| t.set_font(font, 10.0))
+        });
+        assert!(matches!(result, Err(PdfError::InvalidArgument(_))));
+    }
+}
+
+#[test]
+fn info_and_outline_strings_are_escaped() {
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_title("Smile :)");
+    document.set_author("Łódź");
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.add_outline("Chapter (1) \\ α");
+            Ok(())
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/Title (Smile :\\))"));
+    assert!(output.contains("/Author <FEFF014100F30064017A>"));
+    assert!(output.contains("/Title <FEFF0043006800610070007400650072"));
+}
+
+#[test]
+fn same_document_is_written_the_same() {
+    use chrono::{FixedOffset, TimeZone};
+    use pdf_canvas::Pdf;
+    let write = |linearized| {
+        let mut document = Pdf::create_with_buffer().unwrap();
+        document.set_linearized(linearized).unwrap();
+        document.set_title("Again");
+        let date = FixedOffset::west_opt(5 * 3600 + 30 * 60)
+            .unwrap()
+            .with_ymd_and_hms(2020, 2, 29, 12, 0, 0)
+            .unwrap();
+        document.set_creation_date(date);
+        document
+            .render_page(100.0, 100.0, |canvas| {
+                for font in &[
+                    BuiltinFont::Courier,
+                    BuiltinFont::Helvetica,
+                    BuiltinFont::Times_Roman,
+                    BuiltinFont::Symbol,
+                ] {
+                    canvas.left_text(5.0, 5.0, *font, 10.0, "Same")?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        document.finish().unwrap().into_inner()
+    };
+    for &linearized in &[false, true] {
+        let bytes = write(linearized);
+        assert_eq!(bytes, write(linearized));
+        let output = String::from_utf8_lossy(&bytes).into_owned();
+        assert!(output.contains("/CreationDate (D:20200229120000-05'30')"));
+        assert!(output.contains("/ModDate (D:20200229120000-05'30')"));
+        assert!(output.contains(" /ID [ <"));
+    }
+}
+
+#[test]
+fn metadata_is_written_as_xmp() {
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_title("Fish & Chips");
+    document.set_author("Łódź");
+    document.set_keywords("food");
+    let uri = "http://example.com/ns/menu/";
+    document
+        .set_xmp_property(uri, "menu", "Price", "<5")
+        .unwrap();
+    document
+        .render_page(100.0, 100.0, |_canvas| Ok(()))
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/Type /Metadata /Subtype /XML"));
+    assert!(output.contains("/Metadata "));
+    assert!(output.contains(
+        "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Fish &amp; Chips"
+    ));
+    assert!(output.contains("<rdf:li>Łódź</rdf:li>"));
+    assert!(output.contains("<pdf:Keywords>food</pdf:Keywords>"));
+    assert!(output.contains("<xmp:CreateDate>"));
+    assert!(output.contains("xmlns:menu=\"http://example.com/ns/menu/\""));
+    assert!(output.contains("<menu:Price>&lt;5</menu:Price>"));
+}
+
+#[test]
+fn pdf_a_document_is_identified() {
+    use pdf_canvas::{Pdf, PdfA};
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_pdf_a(PdfA::A2b).unwrap();
+    let uri = "http://example.com/ns/statement/";
+    document
+        .set_xmp_property(uri, "st", "Account", "17")
+        .unwrap();
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.rectangle(10.0, 10.0, 50.0, 50.0)?;
+            canvas.fill()
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/OutputIntents [ << /Type /OutputIntent"));
+    assert!(output.contains("/OutputConditionIdentifier (sRGB IEC61966-2.1)"));
+    assert!(output.contains("<pdfaid:part>2</pdfaid:part>"));
+    assert!(output.contains("<pdfaid:conformance>B</pdfaid:conformance>"));
+    assert!(output.contains(
+        "<pdfaSchema:namespaceURI>http://example.com/ns/statement/<"
+    ));
+    assert!(output.contains("<pdfaProperty:name>Account</pdfaProperty:name>"));
+    assert!(output.contains(" /ID [ <"));
+}
+
+#[test]
+fn pdf_a_violations_are_errors() {
+    use pdf_canvas::{Pdf, PdfA, PdfError};
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_pdf_a(PdfA::A1b).unwrap();
+    let result = document.render_page(100.0, 100.0, |canvas| {
+        canvas.left_text(5.0, 5.0, BuiltinFont::Helvetica, 10.0, "Hello")
+    });
+    assert!(matches!(result, Err(PdfError::Conformance(_))));
+
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, 1, 1);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 128]).unwrap();
+    }
+    let result = document.add_png(&png);
+    assert!(matches!(result, Err(PdfError::Conformance(_))));
+    // The font of the failed page is not written, so the document can
+    // still be finished.
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(!output.contains("/BaseFont /Helvetica"));
+
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_pdf_a(PdfA::A2b).unwrap();
+    assert!(document.add_png(&png).is_ok());
+    let result = document.set_pdf_a(PdfA::A1b);
+    assert!(matches!(result, Err(PdfError::Structure(_))));
+}
+
+#[test]
+fn attached_files_are_embedded() {
+    use pdf_canvas::{Pdf, PdfA, PdfError, Relationship};
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_pdf_a(PdfA::A3b).unwrap();
+    let xml = b"<Invoice/>";
+    document
+        .attach_file(
+            "invoice.xml",
+            xml,
+            "text/xml",
+            Relationship::Alternative,
+        )
+        .unwrap();
+    document
+        .attach_file("data.csv", b"a,b", "text/csv", Relationship::Data)
+        .unwrap();
+    let result = document.attach_file(
+        "data.csv",
+        b"c,d",
+        "text/csv",
+        Relationship::Data,
+    );
+    assert!(matches!(result, Err(PdfError::InvalidArgument(_))));
+    document
+        .render_page(100.0, 100.0, |_canvas| Ok(()))
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/Type /EmbeddedFile /Subtype /text#2Fxml"));
+    assert!(output.contains("/AFRelationship /Alternative"));
+    assert!(output.contains("<pdfaid:part>3</pdfaid:part>"));
+    // The names are sorted.
+    let names = &output[output.find("/EmbeddedFiles").unwrap()..];
+    assert!(names.starts_with("/EmbeddedFiles << /Names [ (data.csv) "));
+    assert!(names.contains(" (invoice.xml) "));
+    assert!(output.contains("/AF [ "));
+
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_pdf_a(PdfA::A2b).unwrap();
+    let result =
+        document.attach_file("a.xml", xml, "text/xml", Relationship::Source);
+    assert!(matches!(result, Err(PdfError::Conformance(_))));
+
+    // Files attached before the PDF/A level is set were not checked.
+    for level in [PdfA::A1b, PdfA::A2b] {
+        let mut document = Pdf::create_with_buffer().unwrap();
+        document
+            .attach_file("a.xml", xml, "text/xml", Relationship::Source)
+            .unwrap();
+        let result = document.set_pdf_a(level);
+        assert!(matches!(result, Err(PdfError::Structure(_))));
+    }
+}
+
+#[test]
+fn truetype_font_is_embedded_as_subset() {
+    use pdf_canvas::{Pdf, TrueTypeFont};
+    let font = TrueTypeFont::from_file("data/PdfCanvasTest.ttf").unwrap();
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.left_text(10.0, 50.0, font, 10.0, "AÄ B")
+        })
+        .unwrap();
+    let data = document.finish().unwrap().into_inner();
+    let output = String::from_utf8_lossy(&data).into_owned();
+    let name = &output[output.find("/FontName /").unwrap() + 11..];
+    let (tag, name) = name.split_at(6);
+    assert!(tag.chars().all(|c| c.is_ascii_uppercase()));
+    assert!(name.starts_with("+PdfCanvasTest-Regular\n"));
+    assert!(output.contains("/Subtype /TrueType /BaseFont /"));
+    assert!(output.contains("/FirstChar 32 /LastChar 255"));
+    // The widths of space, A and B, and Ä at 196.
+    assert!(output.contains("/Widths [ 250 500 "));
+    assert!(output.contains(" 500 600 550 500 "));
+    assert!(output.contains(" 500 600 500 "));
+    assert!(output.contains("/Encoding /WinAnsiEncoding"));
+    assert!(output.contains("/FontFile2 "));
+    assert!(!output.contains("/CIDSet"));
+    // The FontFile2 stream is a TrueType font, of length Length1.
+    let find = |text: &[u8], from: usize| {
+        data[from..]
+            .windows(text.len())
+            .position(|w| w == text)
+            .map(|pos| from + pos + text.len())
+            .unwrap()
+    };
+    let start = find(b"/Length1 ", 0);
+    let length = String::from_utf8_lossy(&data[start..start + 10]);
+    let length = length.split(' ').next().unwrap().parse::<usize>().unwrap();
+    let start = find(b">>\nstream\n", start);
+    assert_eq!(b"\0\x01\0\0", &data[start..start + 4]);
+    assert_eq!(b"\nendstream", &data[start + length..start + length + 10]);
+}
+
+#[test]
+fn composite_truetype_font_uses_identity_h() {
+    use pdf_canvas::{Pdf, TrueTypeFont};
+    let font = TrueTypeFont::from_file("data/PdfCanvasTest.ttf")
+        .unwrap()
+        .composite();
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.left_text(10.0, 50.0, font, 10.0, "AÄ😀")
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("<000300050006> Tj"));
+    assert!(output.contains("/Subtype /Type0 /BaseFont /"));
+    assert!(output.contains("+PdfCanvasTest-Regular-Identity-H\n"));
+    assert!(output.contains("/Encoding /Identity-H"));
+    assert!(output.contains("/Subtype /CIDFontType2"));
+    assert!(output.contains("/CIDToGIDMap /Identity"));
+    assert!(output.contains("/W [ 0 [ 500 ] 3 [ 600 ] 5 [ 600 ] 6 [ 600 ] ]"));
+    assert!(output.contains("/FontFile2 "));
+    assert!(output.contains("/CIDSet "));
+}
+
+#[test]
+fn different_fonts_with_the_same_name_are_not_merged() {
+    use pdf_canvas::{Pdf, TrueTypeFont};
+    let mut data = std::fs::read("data/PdfCanvasTest.ttf").unwrap();
+    let font = TrueTypeFont::from_bytes(&data).unwrap();
+    data[16] ^= 1; // the checksum of the first table
+    let other = TrueTypeFont::from_bytes(&data).unwrap();
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.left_text(10.0, 50.0, font.clone(), 10.0, "A")?;
+            canvas.left_text(10.0, 30.0, other, 10.0, "B")?;
+            canvas.left_text(10.0, 10.0, font, 10.0, "C")
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/F0 10 Tf\n10 10 Td\n(C) Tj"));
+    assert!(output.contains("/F1 10 Tf\n10 30 Td\n(B) Tj"));
+    assert!(!output.contains("/F2 "));
+    assert_eq!(2, output.matches("/FontFile2 ").count());
+}
+
+#[test]
+fn symbolic_type1_font_has_widths_for_low_codes() {
+    use pdf_canvas::{Pdf, Type1Font};
+    let afm = "StartFontMetrics 4.1\n\
+               FontName Example-Symbols\n\
+               EncodingScheme FontSpecific\n\
+               StartCharMetrics 2\n\
+               C 20 ; WX 700 ; N a20 ;\n\
+               C 32 ; WX 250 ; N space ;\n\
+               EndCharMetrics\n\
+               EndFontMetrics\n";
+    let font = Type1Font::from_afm(afm.as_bytes()).unwrap();
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.left_text(10.0, 50.0, font, 10.0, "✔ ✔")
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/FirstChar 20 /LastChar 255"));
+    assert!(output.contains("/Widths [ 700 0 0 0 0 0 0 0 0 0 0 0 250 "));
+}
+
+#[test]
+fn kerned_text_is_shown_with_tj_adjustments() {
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            let helvetica = canvas.get_font(BuiltinFont::Helvetica);
+            let courier = canvas.get_font(BuiltinFont::Courier);
+            canvas.text(|t| {
+                t.set_font(&helvetica, 12.0)?;
+                t.pos(10.0, 50.0)?;
+                t.show_kerned("WAVE")?;
+                t.set_font(&courier, 12.0)?;
+                t.show_kerned("WAVE")
+            })
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    // Helvetica has KPX W A -50 and KPX A V -70, but no V E pair.
+    assert!(output.contains("[(W) 50 (A) 70 (VE)] TJ"));
+    // Courier has no kerning.
+    assert!(output.contains("[(WAVE)] TJ"));
+}
+
+#[test]
+fn auto_encoding_applies_to_boxed_and_shared_builtin_fonts() {
+    use pdf_canvas::Pdf;
+    use std::sync::Arc;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_auto_encoding(true);
+    document
+        .render_page(300.0, 100.0, |canvas| {
+            let boxed = Box::new(BuiltinFont::Helvetica);
+            canvas.left_text(10.0, 70.0, boxed, 10.0, "Łódź")?;
+            let shared = Arc::new(BuiltinFont::Courier);
+            canvas.left_text(10.0, 30.0, shared, 10.0, "Gdańsk")
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    // Without automatic encodings, Ł and ń could not be shown.
+    assert_eq!(1, output.matches("/BaseFont /Helvetica").count());
+    assert_eq!(1, output.matches("/BaseFont /Courier").count());
+    assert_eq!(2, output.matches("/Differences").count());
+}
+
+#[test]
+fn auto_encoding_shows_all_of_win_ansi() {
+    use pdf_canvas::Pdf;
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_auto_encoding(true);
+    document
+        .render_page(300.0, 100.0, |canvas| {
+            let font = BuiltinFont::Times_Roman;
+            canvas.left_text(10.0, 50.0, font, 10.0, "cœur ÿ")
+        })
+        .unwrap();
+    let data = document.finish().unwrap().into_inner();
+    // œ and ÿ are in WinAnsiEncoding, so no differences are needed.
+    let shown = b"(c\x9Cur \xFF) Tj";
+    assert!(data.windows(shown.len()).any(|w| w == shown));
+    assert!(!String::from_utf8_lossy(&data).contains("/Differences"));
+}
+
+#[test]
+fn grestore_restores_the_shown_part_of_a_font() {
+    use pdf_canvas::Pdf;
+    // Enough letters not in WinAnsi to fill the first part of the font.
+    let text = "ĀāĂăĄąĆćČčĎďĐđĒēĖėĘęĚěĞğĢģĪīĮįİıĶķĹĺĻļĽľŁłŃńŅņŇňŌōŐő";
+    let mut document = Pdf::create_with_buffer().unwrap();
+    document.set_auto_encoding(true);
+    document
+        .render_page(300.0, 100.0, |canvas| {
+            let font = canvas.get_font(BuiltinFont::Times_Roman);
+            canvas.text(|t| {
+                t.set_font(&font, 10.0)?;
+                t.show(text)?;
+                t.show("H")?;
+                t.gsave()?;
+                t.show("ż")?;
+                t.grestore()?;
+                t.show("ż")
+            })
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    // The Q selects the first part again, so ż needs its part again.
+    assert!(output.contains("q\n/F1 10 Tf\n"));
+    assert!(output.contains("Q\n/F1 10 Tf\n"));
+}
+
+#[test]
+fn jpeg_is_drawn_as_image_xobject() {
+    use pdf_canvas::Pdf;
+    // A JPEG header with an APP0 segment and a 3×2 grayscale frame.
+    let jpeg = [
+        0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46, 0xFF, 0xC0, 0x00,
+        0x0B, 0x08, 0x00, 0x02, 0x00, 0x03, 0x01, 0x01, 0x11, 0x00, 0xFF,
+        0xD9,
+    ];
+    let mut document = Pdf::create_with_buffer().unwrap();
+    let image = document.add_jpeg(&jpeg).unwrap();
+    assert_eq!((3, 2), (image.width(), image.height()));
+    document
+        .render_page(100.0, 100.0, |canvas| {
+            canvas.draw_image(&image, 10.0, 20.0, 30.0, 40.0)
+        })
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/Subtype /Image /Width 3 /Height 2"));
+    assert!(output.contains("/ColorSpace /DeviceGray /BitsPerComponent 8"));
+    assert!(output.contains("/Filter /DCTDecode"));
+    let name = image.to_string();
+    let id = name.trim_start_matches("/Im");
+    assert!(output.contains(&format!("/XObject << {} {} 0 R >>", name, id)));
+    assert!(
+        output.contains(&format!("q\n30 0 0 40 10 20 cm\n{} Do\nQ", name))
+    );
+}
+
+#[test]
+fn fonts_of_a_failed_page_are_not_used_by_the_next_page() {
+    use pdf_canvas::{Pdf, PdfError};
+    let mut document = Pdf::create_with_buffer().unwrap();
+    let result = document.render_page(100.0, 100.0, |canvas| {
+        canvas.left_text(10.0, 10.0, BuiltinFont::Courier, 10.0, "Lost")?;
+        canvas.gsave()
+    });
+    assert!(matches!(result, Err(PdfError::Structure(_))));
+    document
+        .render_page(100.0, 100.0, |canvas| canvas.line(0.0, 0.0, 1.0, 1.0))
+        .unwrap();
+    let output =
+        String::from_utf8_lossy(&document.finish().unwrap().into_inner())
+            .into_owned();
+    assert!(output.contains("/Resources << /Font << >> >>"));
+}